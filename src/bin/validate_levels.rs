@@ -0,0 +1,202 @@
+//! Checks the LDtk project for problems that would otherwise only show up in game.
+//!
+//! `cargo run --bin validate_levels [path/to/world.ldtk]`, exits with a failure
+//! code when there is anything wrong so asset changes can be gated on it.
+
+use std::{fmt, process::ExitCode};
+
+use bevy_ecs_ldtk::ldtk::{EntityInstance, FieldValue, LdtkJson, Level, Type, WorldLayout};
+
+use wasm_multi::config::{
+    ENTITY_IDENTIFIERS, LEVEL_IIDS, NUM_PLAYERS, SPAWN_POINT_ENTITY, wall_int_grid_values,
+};
+
+const DEFAULT_PATH: &str = "assets/world.ldtk";
+
+/// Something wrong with a level, at a grid cell if it has one
+struct Problem {
+    level: String,
+    cell: Option<(i32, i32)>,
+    message: String,
+}
+
+impl Problem {
+    fn new(level: &Level, message: String) -> Self {
+        Problem {
+            level: level.identifier.clone(),
+            cell: None,
+            message,
+        }
+    }
+
+    fn at(mut self, x: i32, y: i32) -> Self {
+        self.cell = Some((x, y));
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell {
+            Some((x, y)) => write!(f, "{} at ({}, {}): {}", self.level, x, y, self.message),
+            None => write!(f, "{}: {}", self.level, self.message),
+        }
+    }
+}
+
+/// Spawn points can be reserved for a player, once per player
+fn check_player_handle(entity: &EntityInstance, reserved: &mut Vec<i32>) -> Option<String> {
+    let handle = entity
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == "player_handle")
+        .and_then(|field| match field.value {
+            FieldValue::Int(handle) => handle,
+            _ => None,
+        })?;
+    if !(0..NUM_PLAYERS as i32).contains(&handle) {
+        return Some(format!(
+            "player_handle {} is not one of the {} players",
+            handle, NUM_PLAYERS
+        ));
+    }
+    if reserved.contains(&handle) {
+        return Some(format!(
+            "player_handle {} has more than one spawn point",
+            handle
+        ));
+    }
+    reserved.push(handle);
+    None
+}
+
+fn validate_level(level: &Level, played: bool, problems: &mut Vec<Problem>) {
+    let Some(layers) = &level.layer_instances else {
+        problems.push(Problem::new(
+            level,
+            "layers are saved to a separate file, which isn't supported".to_string(),
+        ));
+        return;
+    };
+
+    let wall_values: Vec<i32> = wall_int_grid_values().collect();
+    let mut spawns = 0;
+    let mut reserved = Vec::new();
+    for layer in layers {
+        match layer.layer_instance_type {
+            Type::IntGrid => {
+                for (index, value) in layer.int_grid_csv.iter().enumerate() {
+                    if *value != 0 && !wall_values.contains(value) {
+                        let index = index as i32;
+                        problems.push(
+                            Problem::new(
+                                level,
+                                format!(
+                                    "unknown IntGrid value {} in layer {}",
+                                    value, layer.identifier
+                                ),
+                            )
+                            .at(index % layer.c_wid, index / layer.c_wid),
+                        );
+                    }
+                }
+            }
+            Type::Entities => {
+                for entity in &layer.entity_instances {
+                    if entity.identifier == SPAWN_POINT_ENTITY {
+                        spawns += 1;
+                        if let Some(problem) = check_player_handle(entity, &mut reserved) {
+                            problems.push(
+                                Problem::new(level, problem).at(entity.grid.x, entity.grid.y),
+                            );
+                        }
+                    }
+                    if !ENTITY_IDENTIFIERS.contains(&entity.identifier.as_str()) {
+                        problems.push(
+                            Problem::new(
+                                level,
+                                format!("entity {} has no registered bundle", entity.identifier),
+                            )
+                            .at(entity.grid.x, entity.grid.y),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !played {
+        return;
+    }
+    if spawns == 0 {
+        problems.push(Problem::new(level, "no SpawnPoint".to_string()));
+    } else if spawns < NUM_PLAYERS {
+        problems.push(Problem::new(
+            level,
+            format!(
+                "{} SpawnPoints for {} players, they would share spawns",
+                spawns, NUM_PLAYERS
+            ),
+        ));
+    }
+}
+
+fn validate(project: &LdtkJson) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    // Streamed worlds get to every level by walking, otherwise only `LEVEL_IIDS` are played
+    let streamed = matches!(
+        project.world_layout,
+        Some(WorldLayout::GridVania | WorldLayout::Free)
+    );
+    for iid in LEVEL_IIDS {
+        if !project.levels.iter().any(|level| level.iid == iid) {
+            problems.push(Problem {
+                level: iid.to_string(),
+                cell: None,
+                message: "in LEVEL_IIDS but not in the project".to_string(),
+            });
+        }
+    }
+
+    for level in &project.levels {
+        let listed = LEVEL_IIDS.contains(&level.iid.as_str());
+        if !listed && !streamed {
+            problems.push(Problem::new(
+                level,
+                format!("{} is not in LEVEL_IIDS and can't be played", level.iid),
+            ));
+        }
+        validate_level(level, listed, &mut problems);
+    }
+    problems
+}
+
+fn main() -> ExitCode {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PATH.to_string());
+
+    let project = match std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|json| serde_json::from_str::<LdtkJson>(&json).map_err(|error| error.to_string()))
+    {
+        Ok(project) => project,
+        Err(error) => {
+            eprintln!("Couldn't read {}: {}", path, error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let problems = validate(&project);
+    if problems.is_empty() {
+        println!("{} is fine", path);
+        return ExitCode::SUCCESS;
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    eprintln!("{} problems in {}", problems.len(), path);
+    ExitCode::FAILURE
+}
@@ -1,3 +1,4 @@
+use bevy::color::Color;
 use bevy_matchbox::prelude::PeerId;
 
 pub const INPUT_UP: u8 = 1 << 0;
@@ -6,9 +7,142 @@ pub const INPUT_LEFT: u8 = 1 << 2;
 pub const INPUT_RIGHT: u8 = 1 << 3;
 pub const INPUT_FIRE: u8 = 1 << 4;
 pub const INPUT_USE: u8 = 1 << 5;
+/// Not a key: the levels the simulation is in are spawned with their colliders
+/// on the peer the input comes from
+pub const INPUT_LEVELS_READY: u8 = 1 << 6;
 
 pub const PLAYER_Z: f32 = 9.0;
 
+pub const NUM_PLAYERS: usize = 2;
+
 pub type MultiplayerConfig = bevy_ggrs::GgrsConfig<u8, PeerId>;
 
-pub const LEVEL_IIDS: [&str; 1] = ["2d3efb50-1030-11f0-bddd-f1f4e985be26"];
+pub const LEVEL_IIDS: [&str; 2] = [
+    "2d3efb50-1030-11f0-bddd-f1f4e985be26",
+    "6a78041e-cb50-11f1-8009-02fc00000001",
+];
+
+// LDtk entities the plugins register a bundle for
+pub const SPAWN_POINT_ENTITY: &str = "SpawnPoint";
+pub const PLAYER_ENTITY: &str = "Player";
+pub const EXIT_ENTITY: &str = "Exit";
+pub const CHECKPOINT_ENTITY: &str = "Checkpoint";
+pub const LEVER_ENTITY: &str = "Lever";
+pub const BUTTON_ENTITY: &str = "Button";
+pub const DOOR_ENTITY: &str = "Door";
+pub const PICKUP_ENTITY: &str = "Pickup";
+pub const MOVING_PLATFORM_ENTITY: &str = "MovingPlatform";
+
+/// Every LDtk entity with a registered bundle, for the level validation tool
+pub const ENTITY_IDENTIFIERS: [&str; 9] = [
+    SPAWN_POINT_ENTITY,
+    PLAYER_ENTITY,
+    EXIT_ENTITY,
+    CHECKPOINT_ENTITY,
+    LEVER_ENTITY,
+    BUTTON_ENTITY,
+    DOOR_ENTITY,
+    PICKUP_ENTITY,
+    MOVING_PLATFORM_ENTITY,
+];
+
+/// How a wall feels to walk on
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub enum Surface {
+    #[default]
+    Normal,
+    Mud,
+    Ice,
+    Bouncy,
+}
+
+impl Surface {
+    /// Scales the `MovementDampingFactor` of characters standing on it
+    pub fn damping_scale(&self) -> f32 {
+        match self {
+            Surface::Normal | Surface::Bouncy => 1.0,
+            Surface::Mud => 3.0,
+            Surface::Ice => 0.1,
+        }
+    }
+}
+
+pub struct WallMaterial {
+    /// IntGrid value in the Walls layer
+    pub value: i32,
+    pub friction: f32,
+    pub restitution: f32,
+    pub surface: Surface,
+    /// Materials without auto layer tiles are drawn with a plain color
+    pub color: Option<Color>,
+}
+
+pub const WALL_MATERIALS: [WallMaterial; 5] = [
+    // dirt
+    WallMaterial {
+        value: 1,
+        friction: 1.0,
+        restitution: 0.0,
+        surface: Surface::Normal,
+        color: None,
+    },
+    // stone
+    WallMaterial {
+        value: 3,
+        friction: 0.8,
+        restitution: 0.0,
+        surface: Surface::Normal,
+        color: Some(Color::srgb(0.35, 0.41, 0.53)),
+    },
+    // ice
+    WallMaterial {
+        value: 5,
+        friction: 0.05,
+        restitution: 0.0,
+        surface: Surface::Ice,
+        color: Some(Color::srgb(0.65, 0.94, 1.0)),
+    },
+    // mud
+    WallMaterial {
+        value: 6,
+        friction: 1.0,
+        restitution: 0.0,
+        surface: Surface::Mud,
+        color: Some(Color::srgb(0.36, 0.23, 0.13)),
+    },
+    // bounce pad
+    WallMaterial {
+        value: 7,
+        friction: 0.5,
+        restitution: 0.9,
+        surface: Surface::Bouncy,
+        color: Some(Color::srgb(1.0, 0.31, 0.85)),
+    },
+];
+
+/// IntGrid values of the slope tiles, with the height of their surface
+/// on the left and right edge of the tile, in half tiles.
+/// Shallow slopes take two tiles to climb one, a 1:2 gradient of about 26.6°.
+/// It is the closest to 22.5° that still lines up with the grid.
+pub const SLOPE_TILES: [(i32, i32, i32); 6] = [
+    (8, 0, 2),  // 45° up to the right
+    (9, 2, 0),  // 45° up to the left
+    (10, 0, 1), // shallow up to the right, lower half
+    (11, 1, 2), // shallow up to the right, upper half
+    (12, 2, 1), // shallow up to the left, upper half
+    (13, 1, 0), // shallow up to the left, lower half
+];
+
+/// IntGrid value of hazards in the Walls layer, spikes and water
+pub const HAZARD_CELL: i32 = 2;
+/// IntGrid value of one-way platforms in the Walls layer
+pub const ONE_WAY_CELL: i32 = 4;
+
+/// Every value of the `Walls` IntGrid layer the wall plugin registers a bundle for
+pub fn wall_int_grid_values() -> impl Iterator<Item = i32> {
+    WALL_MATERIALS
+        .iter()
+        .map(|material| material.value)
+        .chain(SLOPE_TILES.iter().map(|(value, ..)| *value))
+        .chain([HAZARD_CELL, ONE_WAY_CELL])
+}
@@ -3,5 +3,26 @@ use bevy::prelude::*;
 pub enum GameState {
     #[default]
     Loading,
-    Playing,
+    /// An asset failed to load, waiting for the player to retry
+    LoadingFailed,
+    /// Picking how to play, nothing is connected
+    MainMenu,
+    /// The socket is open, waiting for the matchmaking server to let us in
+    Connecting,
+    /// In a room, waiting for the other players to join and get ready
+    Lobby,
+    /// A GGRS session is running
+    InGame,
+    /// The match is over, peers can rematch or go back to the menu
+    Results,
+}
+
+/// The pause menu of a running match. The other players keep playing,
+/// pausing only holds back our inputs.
+#[derive(SubStates, Default, Clone, Eq, PartialEq, Hash, Debug)]
+#[source(GameState = GameState::InGame)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
 }
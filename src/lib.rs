@@ -0,0 +1,3 @@
+//! Parts of the game the tools in `src/bin` share with it
+
+pub mod config;
@@ -3,29 +3,43 @@ use std::process;
 use avian2d::prelude::*;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
+use bevy_egui::EguiPlugin;
 use bevy_ggrs::GgrsTime;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use config::LEVEL_IIDS;
-use game::GameState;
+use game::{GameState, PauseState};
 use systems::{
+    LoadingFailure, LoadingProgress,
+    bots::BotPlugin,
+    chat::ChatPlugin,
     check_asset_loading,
+    checkpoints::{CheckpointPlugin, touch_checkpoints},
     controller::{ControllerPlugin, MovementEvent, process_inputs},
+    death::{DeathPlugin, disable_dead_colliders, kill_players, respawn_dead_players},
     frame_logging::{
         CurrentSessionFrame, RollbackStatus, update_current_session_frame, update_rollback_status,
     },
-    multiplayer::MultiplayerPlugin,
+    interactibles::{InteractiblePlugin, interact, update_interactible_colliders},
+    levels::{LevelPlugin, check_level_exit, enter_next_level, restart_reloaded_level},
+    loading_failed_screen, loading_screen,
+    multiplayer::{MultiplayerPlugin, synctest::in_sync_test},
+    parallax::ParallaxPlugin,
+    pixel_perfect::PixelPerfectPlugin,
+    platforms::{PlatformPlugin, move_platforms, start_drop_through},
     player::{
         PlayerPlugin,
-        camera::camera_follow_local_players,
+        camera::{arrange_cameras, camera_follow_local_players},
         movement::{
-            apply_gravity, apply_movement_damping, kinematic_controller_collisions, move_players,
-            update_grounded,
+            apply_gravity, apply_movement_damping, bounce_on_pads, carry_on_platforms,
+            kinematic_controller_collisions, move_players, update_grounded,
         },
     },
+    scoreboard::ScoreboardPlugin,
+    streaming::{StreamingPlugin, confine_to_streamed_levels, track_streamed_levels},
 };
+// Also makes `crate::config` available to the modules below
+use wasm_multi::config::{self, LEVEL_IIDS};
 
 mod components;
-mod config;
 mod game;
 mod systems;
 
@@ -47,15 +61,29 @@ fn main() {
         LdtkPlugin,
         PlayerPlugin,
         MultiplayerPlugin,
+        (
+            LevelPlugin,
+            CheckpointPlugin,
+            InteractiblePlugin,
+            DeathPlugin,
+            PlatformPlugin,
+            StreamingPlugin,
+        ),
+        (ChatPlugin, ScoreboardPlugin),
+        BotPlugin,
         ControllerPlugin,
+        EguiPlugin,
         WorldInspectorPlugin::new(),
         PhysicsDebugPlugin::default(),
         systems::walls::WallPlugin,
+        (ParallaxPlugin, PixelPerfectPlugin),
     ))
     .insert_resource(LdtkSettings {
         level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
+            // Streamed worlds pick the levels to load themselves
             load_level_neighbors: false,
         },
+        // The parallax background is drawn instead
         set_clear_color: SetClearColor::No,
         ..Default::default()
     })
@@ -63,10 +91,18 @@ fn main() {
     .insert_resource(RollbackStatus::default())
     .insert_resource(CurrentSessionFrame::default())
     .init_state::<GameState>()
+    .add_sub_state::<PauseState>()
     .add_systems(Startup, systems::setup)
+    .init_resource::<LoadingProgress>()
     .add_systems(
         Update,
-        (check_asset_loading.run_if(in_state(GameState::Loading)),),
+        (
+            check_asset_loading
+                .run_if(in_state(GameState::Loading).or(in_state(GameState::LoadingFailed))),
+            loading_screen.run_if(in_state(GameState::Loading)),
+            loading_failed_screen
+                .run_if(in_state(GameState::LoadingFailed).and(resource_exists::<LoadingFailure>)),
+        ),
     )
     .insert_resource(LevelSelection::Iid(LevelIid::new(LEVEL_IIDS[0])));
 
@@ -79,7 +115,9 @@ fn main() {
 
     app.add_systems(
         Update,
-        camera_follow_local_players.run_if(in_state(GameState::Playing)),
+        (arrange_cameras, camera_follow_local_players)
+            .chain()
+            .run_if(in_state(GameState::InGame)),
     );
 
     app.add_systems(
@@ -88,11 +126,30 @@ fn main() {
             update_current_session_frame,
             update_rollback_status,
             process_inputs,
-            update_grounded,
-            move_players,
-            apply_movement_damping,
-            apply_gravity,
-            kinematic_controller_collisions,
+            move_platforms,
+            // Tuples only go so long, movement is chained on its own
+            (
+                start_drop_through,
+                update_grounded,
+                move_players,
+                bounce_on_pads,
+                apply_movement_damping,
+                apply_gravity,
+                carry_on_platforms,
+                kinematic_controller_collisions,
+            )
+                .chain(),
+            touch_checkpoints,
+            kill_players,
+            respawn_dead_players,
+            disable_dead_colliders,
+            interact,
+            update_interactible_colliders,
+            restart_reloaded_level.run_if(in_sync_test),
+            check_level_exit,
+            enter_next_level,
+            track_streamed_levels,
+            confine_to_streamed_levels,
             apply_deferred,
         )
             .chain()
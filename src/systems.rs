@@ -1,7 +1,18 @@
+pub mod bots;
+pub mod chat;
+pub mod checkpoints;
 pub mod colliders;
 pub mod controller;
+pub mod death;
+pub mod interactibles;
+pub mod levels;
 pub mod multiplayer;
+pub mod parallax;
+pub mod pixel_perfect;
+pub mod platforms;
 pub mod player;
+pub mod scoreboard;
+pub mod streaming;
 pub mod walls;
 
 pub mod frame_logging;
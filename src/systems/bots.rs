@@ -0,0 +1,119 @@
+pub mod navigation;
+
+use avian2d::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ggrs::{LocalPlayers, ReadInputs};
+use navigation::{JUMP_DISTANCE, NavGraph};
+
+use crate::config::*;
+
+use super::{
+    controller::Grounded, multiplayer::read_local_inputs, player::Player, walls::WallGeometry,
+};
+
+/// Bots stop steering this close to where they want to be
+const ARRIVE_DISTANCE: f32 = 4.0;
+/// How close to a ledge or gap a bot gets before jumping
+const JUMP_TRIGGER_DISTANCE: f32 = JUMP_DISTANCE / 2.;
+
+/// A player whose inputs come from the AI of the peer owning it
+#[derive(Component, Default, Clone)]
+pub struct Bot;
+
+/// Inputs the AI picked this frame, keyed by player handle.
+/// `read_local_inputs` sends them like any other local input.
+#[derive(Resource, Default)]
+pub struct BotInputs(pub HashMap<usize, u8>);
+
+pub fn build_nav_graph(mut nav_graph: ResMut<NavGraph>, wall_geometry: Res<WallGeometry>) {
+    let walls: Vec<Rect> = wall_geometry.0.values().flatten().copied().collect();
+    *nav_graph = NavGraph::new(&walls);
+}
+
+/// Picks the inputs of the bots this peer owns
+pub fn drive_bots(
+    mut bot_inputs: ResMut<BotInputs>,
+    local_players: Res<LocalPlayers>,
+    nav_graph: Res<NavGraph>,
+    bots: Query<(&Player, &Position, Has<Grounded>), With<Bot>>,
+    humans: Query<&Position, (With<Player>, Without<Bot>)>,
+) {
+    bot_inputs.0.clear();
+
+    for (player, position, grounded) in &bots {
+        if !local_players.0.contains(&player.handle) {
+            continue;
+        }
+
+        let input = humans
+            .iter()
+            .min_by(|a, b| {
+                a.distance_squared(position.0)
+                    .total_cmp(&b.distance_squared(position.0))
+            })
+            .map(|goal| steer(&nav_graph, position.0, grounded, goal.0))
+            .unwrap_or_default();
+
+        bot_inputs.0.insert(player.handle, input);
+    }
+}
+
+/// Walks towards the goal, hopping between platforms along the shortest path
+fn steer(nav_graph: &NavGraph, position: Vec2, grounded: bool, goal: Vec2) -> u8 {
+    let mut target_x = goal.x;
+    let mut jump = false;
+
+    let here = nav_graph.platform_below(position);
+    let there = nav_graph.platform_below(goal);
+
+    if let (Some(here), Some(there)) = (here, there) {
+        if let Some(next) = nav_graph.next_hop(here, there).filter(|next| *next != here) {
+            let current = nav_graph.platforms[here];
+            let next = nav_graph.platforms[next];
+            // Closest point of the next platform, not too close to its edges
+            target_x = position
+                .x
+                .max(next.left + ARRIVE_DISTANCE)
+                .min(next.right - ARRIVE_DISTANCE);
+
+            let distance = next.distance_x(position.x);
+            let to_edge = if target_x > position.x {
+                current.right - position.x
+            } else {
+                position.x - current.left
+            };
+            // Jump up to higher platforms, and over gaps we would otherwise fall into
+            jump = if next.top > current.top {
+                distance <= JUMP_TRIGGER_DISTANCE
+            } else {
+                distance > 0. && to_edge <= ARRIVE_DISTANCE
+            };
+        }
+    }
+
+    let mut input = 0u8;
+    if target_x > position.x + ARRIVE_DISTANCE {
+        input |= INPUT_RIGHT;
+    } else if target_x < position.x - ARRIVE_DISTANCE {
+        input |= INPUT_LEFT;
+    }
+    if jump && grounded {
+        input |= INPUT_FIRE;
+    }
+    input
+}
+
+/// AI players that go through the same input pipeline as the humans
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BotInputs>()
+            .init_resource::<NavGraph>()
+            .add_systems(
+                Update,
+                build_nav_graph.run_if(resource_changed::<WallGeometry>),
+            )
+            .add_systems(ReadInputs, drive_bots.before(read_local_inputs));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+/// Highest ledge a bot tries to jump onto.
+/// A jump peaks at (3000 / 60)² / (2 * 84) ≈ 14.9 px, keep some margin.
+pub const JUMP_HEIGHT: f32 = 14.0;
+/// Widest gap a bot tries to jump across, about `MOVE_SPEED` times the air time
+pub const JUMP_DISTANCE: f32 = 22.0;
+/// Furthest a bot is willing to drop down
+const DROP_HEIGHT: f32 = 96.0;
+
+/// Walkable top surface of a wall rectangle
+#[derive(Debug, Clone, Copy)]
+pub struct Platform {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+}
+
+impl Platform {
+    /// Horizontal distance to the platform, 0 when above or below it
+    pub fn distance_x(&self, x: f32) -> f32 {
+        (self.left - x).max(x - self.right).max(0.)
+    }
+
+    fn gap_to(&self, other: &Platform) -> f32 {
+        (other.left - self.right)
+            .max(self.left - other.right)
+            .max(0.)
+    }
+}
+
+/// Platforms connected by the jumps and drops a bot can make
+#[derive(Resource, Default)]
+pub struct NavGraph {
+    pub platforms: Vec<Platform>,
+    edges: Vec<Vec<usize>>,
+}
+
+impl NavGraph {
+    pub fn new(walls: &[Rect]) -> Self {
+        let mut platforms: Vec<Platform> = walls
+            .iter()
+            .map(|wall| Platform {
+                left: wall.min.x,
+                right: wall.max.x,
+                top: wall.max.y,
+            })
+            // A wall sitting right on top of another covers its surface
+            .filter(|platform| {
+                !walls.iter().any(|wall| {
+                    wall.min.y == platform.top
+                        && wall.min.x <= platform.left
+                        && wall.max.x >= platform.right
+                })
+            })
+            .collect();
+
+        // Wall geometry comes out of a HashMap, sort it so the same level
+        // always gives the same graph
+        platforms.sort_by(|a, b| {
+            a.top
+                .total_cmp(&b.top)
+                .then(a.left.total_cmp(&b.left))
+                .then(a.right.total_cmp(&b.right))
+        });
+
+        let edges = platforms
+            .iter()
+            .map(|from| {
+                platforms
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, to)| {
+                        let rise = to.top - from.top;
+                        let gap = from.gap_to(to);
+                        if rise > 0. {
+                            rise <= JUMP_HEIGHT && gap <= JUMP_DISTANCE
+                        } else {
+                            -rise <= DROP_HEIGHT && gap <= JUMP_DISTANCE
+                        }
+                    })
+                    .map(|(index, _)| index)
+                    .collect()
+            })
+            .collect();
+
+        Self { platforms, edges }
+    }
+
+    /// The platform something at `point` stands on or falls towards
+    pub fn platform_below(&self, point: Vec2) -> Option<usize> {
+        self.platforms
+            .iter()
+            .enumerate()
+            .filter(|(_, platform)| {
+                platform.top <= point.y + 1. && platform.distance_x(point.x) == 0.
+            })
+            .max_by(|(_, a), (_, b)| a.top.total_cmp(&b.top))
+            .map(|(index, _)| index)
+    }
+
+    /// First platform to go to on the shortest way from `from` to `to`
+    pub fn next_hop(&self, from: usize, to: usize) -> Option<usize> {
+        if from == to {
+            return Some(to);
+        }
+
+        let mut came_from = vec![None; self.platforms.len()];
+        let mut queue = VecDeque::from([from]);
+        came_from[from] = Some(from);
+
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                // Walk back to the platform right after `from`
+                let mut step = to;
+                while came_from[step] != Some(from) {
+                    step = came_from[step]?;
+                }
+                return Some(step);
+            }
+            for &next in &self.edges[current] {
+                if came_from[next].is_none() {
+                    came_from[next] = Some(current);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,159 @@
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+
+use crate::game::GameState;
+
+use super::multiplayer::{
+    identity::{LocalIdentity, PeerIdentities},
+    messages::{Emote, PeerMessage, PeerMessageEvent, PeerMessageExt},
+};
+
+/// Oldest lines get dropped past this
+const MAX_CHAT_LINES: usize = 100;
+/// Characters in one message, longer ones from other peers get cut off
+const MAX_CHAT_LENGTH: usize = 200;
+
+pub enum ChatContent {
+    Text(String),
+    Emote(Emote),
+}
+
+pub struct ChatLine {
+    /// `None` for our own messages
+    pub sender: Option<PeerId>,
+    pub content: ChatContent,
+}
+
+#[derive(Resource, Default)]
+pub struct ChatLog(Vec<ChatLine>);
+
+impl ChatLog {
+    pub fn push(&mut self, line: ChatLine) {
+        self.0.push(line);
+        if self.0.len() > MAX_CHAT_LINES {
+            self.0.remove(0);
+        }
+    }
+}
+
+/// Trims what came over the network into something we are willing to draw
+fn sanitized(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_CHAT_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+pub fn collect_chat_messages(
+    mut messages: EventReader<PeerMessageEvent>,
+    mut log: ResMut<ChatLog>,
+) {
+    for event in messages.read() {
+        let content = match &event.message {
+            PeerMessage::Chat(text) => {
+                let text = sanitized(text);
+                if text.is_empty() {
+                    continue;
+                }
+                ChatContent::Text(text)
+            }
+            PeerMessage::Emote(emote) => ChatContent::Emote(*emote),
+            _ => continue,
+        };
+        log.push(ChatLine {
+            sender: Some(event.peer),
+            content,
+        });
+    }
+}
+
+pub fn chat_window(
+    mut contexts: EguiContexts,
+    mut socket: ResMut<MatchboxSocket>,
+    mut log: ResMut<ChatLog>,
+    mut draft: Local<String>,
+    local_identity: Res<LocalIdentity>,
+    identities: Res<PeerIdentities>,
+) {
+    egui::Window::new("Chat")
+        .resizable(false)
+        .default_width(240.)
+        .anchor(egui::Align2::LEFT_BOTTOM, [8., -8.])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(120.)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &log.0 {
+                        let sender = match line.sender {
+                            Some(peer) => identities.identity(peer),
+                            None => local_identity.0.clone(),
+                        };
+                        ui.horizontal_wrapped(|ui| {
+                            ui.spacing_mut().item_spacing.x = 0.;
+                            match &line.content {
+                                ChatContent::Text(text) => {
+                                    ui.colored_label(sender.egui_color(), sender.name);
+                                    ui.label(format!(": {}", text));
+                                }
+                                ChatContent::Emote(emote) => {
+                                    ui.label("* ");
+                                    ui.colored_label(sender.egui_color(), sender.name);
+                                    ui.label(format!(" {}", emote.verb()));
+                                }
+                            }
+                        });
+                    }
+                });
+
+            let response =
+                ui.add(egui::TextEdit::singleline(&mut *draft).char_limit(MAX_CHAT_LENGTH));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let text = sanitized(&draft);
+                draft.clear();
+                if !text.is_empty() {
+                    socket.broadcast_message(&PeerMessage::Chat(text.clone()));
+                    log.push(ChatLine {
+                        sender: None,
+                        content: ChatContent::Text(text),
+                    });
+                }
+            }
+
+            ui.horizontal(|ui| {
+                for emote in Emote::ALL {
+                    if ui.small_button(emote.label()).clicked() {
+                        socket.broadcast_message(&PeerMessage::Emote(emote));
+                        log.push(ChatLine {
+                            sender: None,
+                            content: ChatContent::Emote(emote),
+                        });
+                    }
+                }
+            });
+        });
+}
+
+/// Every room starts with an empty chat
+pub fn clear_chat(mut log: ResMut<ChatLog>) {
+    log.0.clear();
+}
+
+/// Text chat and emotes over the reliable channel, in the lobby and in game
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatLog>()
+            .add_systems(OnEnter(GameState::MainMenu), clear_chat)
+            .add_systems(
+                Update,
+                (collect_chat_messages, chat_window).chain().run_if(
+                    resource_exists::<MatchboxSocket>.and(not(in_state(GameState::Connecting))),
+                ),
+            );
+    }
+}
@@ -0,0 +1,103 @@
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::Rollback;
+
+use crate::config::CHECKPOINT_ENTITY;
+
+use super::{
+    controller::collision_masks::LayerEnum,
+    multiplayer::{Local, handshake::RollbackRegistryExt},
+};
+
+const CHECKPOINT_COLOR: Color = Color::srgba(0.25, 0.55, 0.65, 0.6);
+const ACTIVE_CHECKPOINT_COLOR: Color = Color::srgba(0.2, 0.9, 1.0, 0.9);
+
+#[derive(Component, Default)]
+pub struct Checkpoint;
+
+#[derive(Bundle, Default)]
+pub struct CheckpointBundle {
+    checkpoint: Checkpoint,
+    sprite: Sprite,
+    collider: Collider,
+    sensor: Sensor,
+    rigid_body: RigidBody,
+    collision_layers: CollisionLayers,
+}
+
+impl LdtkEntity for CheckpointBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let size = IVec2::new(entity_instance.width, entity_instance.height).as_vec2();
+        CheckpointBundle {
+            checkpoint: Checkpoint,
+            sprite: Sprite::from_color(CHECKPOINT_COLOR, size),
+            collider: Collider::rectangle(size.x, size.y),
+            sensor: Sensor,
+            rigid_body: RigidBody::Static,
+            collision_layers: CollisionLayers::new(LayerEnum::Checkpoint, [LayerEnum::Player]),
+        }
+    }
+}
+
+/// Where a player comes back after dying, `None` until it touches a checkpoint
+#[derive(Component, Default, Clone)]
+pub struct RespawnPoint(pub Option<Vec2>);
+
+/// Remembers the last checkpoint each player touched
+pub fn touch_checkpoints(
+    mut players: Query<(&Position, &CollidingEntities, &mut RespawnPoint), With<Rollback>>,
+    checkpoints: Query<&GlobalTransform, With<Checkpoint>>,
+) {
+    for (position, colliding, mut respawn) in &mut players {
+        // Entities are iterated in hash order, pick by position so every peer agrees
+        let touched = colliding
+            .iter()
+            .filter_map(|entity| checkpoints.get(*entity).ok())
+            .map(|transform| transform.translation().truncate())
+            .min_by(|a, b| {
+                a.distance_squared(position.0)
+                    .total_cmp(&b.distance_squared(position.0))
+            });
+
+        if touched.is_some() {
+            respawn.0 = touched;
+        }
+    }
+}
+
+/// Lights up the checkpoints our own players will respawn at
+pub fn show_active_checkpoints(
+    mut checkpoints: Query<(&GlobalTransform, &mut Sprite), With<Checkpoint>>,
+    players: Query<&RespawnPoint, With<Local>>,
+) {
+    for (transform, mut sprite) in &mut checkpoints {
+        let position = transform.translation().truncate();
+        let active = players
+            .iter()
+            .any(|respawn| respawn.0.is_some_and(|point| point.distance(position) < 1.));
+        sprite.color = if active {
+            ACTIVE_CHECKPOINT_COLOR
+        } else {
+            CHECKPOINT_COLOR
+        };
+    }
+}
+
+/// Checkpoint sensors players respawn at after dying
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_rollback_component::<RespawnPoint>()
+            .register_ldtk_entity::<CheckpointBundle>(CHECKPOINT_ENTITY)
+            .add_systems(Update, show_active_checkpoints);
+    }
+}
@@ -2,7 +2,7 @@ use avian2d::prelude::*;
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 
-use super::controller::collision_masks::LayerEnum;
+use super::{controller::collision_masks::LayerEnum, walls::steepest_slope_angle};
 #[derive(Clone, Default, Bundle, LdtkIntCell)]
 pub struct ColliderBundle {
     pub collider: Collider,
@@ -20,6 +20,16 @@ pub enum CharacterCollider {
     Player,
 }
 
+impl CharacterCollider {
+    /// Steepest ground the character stands on instead of sliding down, in radians
+    pub fn max_slope_angle(&self) -> f32 {
+        match self {
+            // A bit over the steepest ramps so they always count as ground
+            CharacterCollider::Player => steepest_slope_angle() + 5f32.to_radians(),
+        }
+    }
+}
+
 // collider: Collider::rectangle(16., 20.),
 // rigid_body: RigidBody::Dynamic,
 // constraints,
@@ -46,6 +56,7 @@ impl From<CharacterCollider> for CollisionLayers {
                     LayerEnum::Wall,
                     LayerEnum::Interactible,
                     LayerEnum::Checkpoint,
+                    LayerEnum::Hazard,
                 ],
             ),
         }
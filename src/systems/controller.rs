@@ -6,9 +6,9 @@ use bevy_ggrs::{PlayerInputs, Rollback};
 use collision_masks::LayerEnum;
 
 use crate::config::*;
-use crate::systems::player::Player;
+use crate::systems::{death::Dead, player::Player};
 
-use super::colliders::CharacterCollider;
+use super::{colliders::CharacterCollider, walls::Surface};
 
 pub mod collision_masks;
 
@@ -19,6 +19,10 @@ pub enum MovementAction {
     Move(usize, Vec2),
     /// Jump (handle)
     Jump(usize),
+    /// Use whatever interactible is in reach (handle)
+    Interact(usize),
+    /// Fall through the one-way platform below (handle)
+    DropThrough(usize),
 }
 
 /// Event for player actions
@@ -35,6 +39,18 @@ pub struct CharacterController;
 #[derive(Component, Default, Clone)]
 pub struct Grounded;
 
+/// What a grounded character stands on
+#[derive(Component, Default, Clone)]
+pub struct GroundSurface(pub Surface);
+
+/// A grounded character that only stands on one-way platforms, and can drop through them
+#[derive(Component, Default, Clone)]
+pub struct OnOneWayPlatform;
+
+/// Velocity of the moving platform a grounded character stands on, which carries it along
+#[derive(Component, Default, Clone)]
+pub struct GroundVelocity(pub Vec2);
+
 #[derive(Clone, Default, Component)]
 pub struct MovementAcceleration(pub Scalar);
 
@@ -104,7 +120,7 @@ impl From<CharacterCollider> for CharacterControllerBundle {
                 let accel = 7.0 * 60.;
                 let damping = 5.;
                 let jump_impulse = 3000.;
-                let slope_angle = (45. as Scalar).to_radians();
+                let slope_angle = value.max_slope_angle();
                 let jump_height = 100.;
                 Self::new(collider, LayerEnum::Wall, collision_mask).with_movement(
                     accel,
@@ -164,9 +180,9 @@ impl CharacterControllerBundle {
 pub fn process_inputs(
     inputs: Res<PlayerInputs<MultiplayerConfig>>,
     mut movement_writer: EventWriter<MovementEvent>,
-    players: Query<(Entity, &Player), With<Rollback>>,
+    players: Query<(Entity, &Player, Has<OnOneWayPlatform>), (With<Rollback>, Without<Dead>)>,
 ) {
-    for (entity, player) in &players {
+    for (entity, player, on_one_way_platform) in &players {
         let (input, input_status) = inputs[player.handle];
         match input_status {
             InputStatus::Disconnected => continue,
@@ -192,10 +208,16 @@ pub fn process_inputs(
         // Add movement action if direction is non-zero
         actions.push(MovementAction::Move(player.handle, direction));
 
-        // Check for jump/other actions
-        if input & INPUT_FIRE != 0 {
+        // Check for jump/other actions, down and jump drops through platforms instead.
+        // Anywhere else it is just a jump.
+        if input & INPUT_FIRE != 0 && input & INPUT_DOWN != 0 && on_one_way_platform {
+            actions.push(MovementAction::DropThrough(player.handle));
+        } else if input & INPUT_FIRE != 0 {
             actions.push(MovementAction::Jump(player.handle));
         }
+        if input & INPUT_USE != 0 {
+            actions.push(MovementAction::Interact(player.handle));
+        }
 
         movement_writer.send(MovementEvent {
             handle: player.handle,
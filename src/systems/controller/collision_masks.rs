@@ -11,5 +11,6 @@ pub enum LayerEnum {
     Player,
     Interactible,
     Checkpoint,
+    Hazard,
     None,
 }
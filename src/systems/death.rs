@@ -0,0 +1,149 @@
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::{PlayerInputs, Rollback, RollbackFrameCount};
+
+use crate::config::MultiplayerConfig;
+
+use super::{
+    checkpoints::RespawnPoint,
+    colliders::CharacterCollider,
+    frame_logging::RollbackStatus,
+    levels::{LevelProgress, all_levels_ready, find_level, world_bottom},
+    multiplayer::handshake::RollbackRegistryExt,
+    player::{Player, level_spawns, spawn_for_handle},
+    walls::HazardZone,
+};
+
+/// How far below the level bounds the kill plane is
+const KILL_PLANE_MARGIN: f32 = 32.0;
+/// Frames between dying and coming back
+const RESPAWN_FRAMES: i32 = 90;
+
+/// A player that died and comes back on `respawn_at_frame`.
+/// Players join a match dead, so their first spawn happens in the simulation too.
+#[derive(Component, Clone, Debug)]
+pub struct Dead {
+    pub respawn_at_frame: i32,
+}
+
+/// Kills players touching a hazard or falling below the level
+#[allow(clippy::too_many_arguments)]
+pub fn kill_players(
+    mut commands: Commands,
+    progress: Res<LevelProgress>,
+    players: Query<
+        (Entity, &Player, &Position, &CollidingEntities),
+        (With<Rollback>, Without<Dead>),
+    >,
+    hazards: Query<(), With<HazardZone>>,
+    frame: Res<RollbackFrameCount>,
+    rollback_status: Res<RollbackStatus>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let project = ldtk_project_assets.get(ldtk_projects.single());
+    let frame: i32 = (*frame).into();
+
+    for (entity, player, position, colliding) in &players {
+        let hit_hazard = colliding.iter().any(|entity| hazards.contains(*entity));
+        let fell = project
+            .and_then(|project| world_bottom(project, &progress, position.x))
+            .is_some_and(|bottom| position.y < bottom - KILL_PLANE_MARGIN);
+        if !hit_hazard && !fell {
+            continue;
+        }
+
+        // Only the first time the frame is simulated, not again on every resimulation
+        if !rollback_status.is_replay {
+            info!("Player {} died on frame {}", player.handle, frame);
+        }
+        commands.entity(entity).insert(Dead {
+            respawn_at_frame: frame + RESPAWN_FRAMES,
+        });
+    }
+}
+
+/// Holds dead players in place, then brings them back at their last checkpoint,
+/// or a spawn point if they haven't reached one. Only once every peer has the
+/// levels loaded, players would fall through missing walls otherwise.
+#[allow(clippy::too_many_arguments)]
+pub fn respawn_dead_players(
+    mut commands: Commands,
+    progress: Res<LevelProgress>,
+    mut players: Query<
+        (
+            Entity,
+            &Player,
+            &Dead,
+            &RespawnPoint,
+            &mut Position,
+            &mut LinearVelocity,
+        ),
+        With<Rollback>,
+    >,
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<MultiplayerConfig>>,
+    rollback_status: Res<RollbackStatus>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let spawns = ldtk_project_assets
+        .get(ldtk_projects.single())
+        .and_then(|project| find_level(project, progress.level_iid()))
+        .map(level_spawns)
+        .unwrap_or_default();
+    let frame: i32 = (*frame).into();
+
+    for (entity, player, dead, respawn, mut position, mut velocity) in &mut players {
+        velocity.0 = Vec2::ZERO;
+        if frame < dead.respawn_at_frame || !all_levels_ready(&inputs) {
+            continue;
+        }
+
+        let target = respawn
+            .0
+            .or_else(|| spawn_for_handle(&spawns, player.handle));
+        let Some(target) = target else {
+            continue;
+        };
+        if !rollback_status.is_replay {
+            info!("Player {} respawned at {}", player.handle, target);
+        }
+        position.0 = target;
+        commands.entity(entity).remove::<Dead>();
+    }
+}
+
+/// Dead players touch nothing until they respawn, no hazards, checkpoints or pickups
+pub fn disable_dead_colliders(
+    mut players: Query<(&mut CollisionLayers, Has<Dead>), (With<Player>, With<Rollback>)>,
+) {
+    for (mut layers, dead) in &mut players {
+        layers.set_if_neq(if dead {
+            CollisionLayers::NONE
+        } else {
+            CharacterCollider::Player.into()
+        });
+    }
+}
+
+pub fn hide_dead_players(mut players: Query<(&mut Visibility, Has<Dead>), With<Player>>) {
+    for (mut visibility, dead) in &mut players {
+        visibility.set_if_neq(if dead {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        });
+    }
+}
+
+/// Hazards and the kill plane below the level, and respawning afterwards
+pub struct DeathPlugin;
+
+impl Plugin for DeathPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_rollback_component::<Dead>()
+            .add_systems(Update, hide_dead_players);
+    }
+}
@@ -0,0 +1,347 @@
+use avian2d::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::{Rollback, RollbackFrameCount};
+
+use crate::config::{BUTTON_ENTITY, DOOR_ENTITY, LEVER_ENTITY, PICKUP_ENTITY};
+
+use super::{
+    controller::{MovementAction, MovementEvent, collision_masks::LayerEnum},
+    multiplayer::handshake::RollbackRegistryExt,
+    player::Player,
+};
+
+/// How long a button keeps its door open
+const BUTTON_FRAMES: i32 = 180;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InteractibleKind {
+    Lever,
+    Button,
+    Door,
+    Pickup,
+}
+
+impl InteractibleKind {
+    pub const ALL: [InteractibleKind; 4] = [
+        InteractibleKind::Lever,
+        InteractibleKind::Button,
+        InteractibleKind::Door,
+        InteractibleKind::Pickup,
+    ];
+
+    /// The LDtk entity it is placed as
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            InteractibleKind::Lever => LEVER_ENTITY,
+            InteractibleKind::Button => BUTTON_ENTITY,
+            InteractibleKind::Door => DOOR_ENTITY,
+            InteractibleKind::Pickup => PICKUP_ENTITY,
+        }
+    }
+
+    fn from_identifier(identifier: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.identifier() == identifier)
+    }
+
+    fn color(&self, active: bool) -> Color {
+        match (self, active) {
+            (InteractibleKind::Lever, false) => Color::srgb(0.9, 0.25, 0.25),
+            (InteractibleKind::Lever, true) => Color::srgb(0.3, 0.9, 0.3),
+            (InteractibleKind::Button, false) => Color::srgb(0.95, 0.45, 0.15),
+            (InteractibleKind::Button, true) => Color::srgb(1.0, 0.85, 0.3),
+            (InteractibleKind::Door, false) => Color::srgb(0.55, 0.43, 0.36),
+            (InteractibleKind::Door, true) => Color::srgba(0.55, 0.43, 0.36, 0.2),
+            (InteractibleKind::Pickup, _) => Color::srgb(0.4, 0.8, 0.3),
+        }
+    }
+}
+
+/// Something in the level players can use with `INPUT_USE`
+#[derive(Component, Clone, Debug)]
+pub struct Interactible {
+    /// The LDtk iid, which unlike the entity is the same on every peer
+    pub iid: String,
+    pub kind: InteractibleKind,
+    /// Iid of the door a lever or button opens
+    pub door: Option<String>,
+}
+
+impl Interactible {
+    fn new(entity_instance: &EntityInstance) -> Self {
+        // The bundles are only registered for the identifiers of `InteractibleKind::ALL`
+        let kind = InteractibleKind::from_identifier(&entity_instance.identifier)
+            .unwrap_or_else(|| panic!("{} is not an interactible", entity_instance.identifier));
+        Interactible {
+            iid: entity_instance.iid.clone(),
+            kind,
+            door: entity_instance
+                .get_maybe_entity_ref_field("Door")
+                .ok()
+                .and_then(Option::as_ref)
+                .map(|door| door.entity_iid.clone()),
+        }
+    }
+}
+
+/// Levers, buttons and pickups, touched through a sensor
+#[derive(Bundle)]
+pub struct InteractibleBundle {
+    interactible: Interactible,
+    sprite: Sprite,
+    collider: Collider,
+    sensor: Sensor,
+    rigid_body: RigidBody,
+    collision_layers: CollisionLayers,
+}
+
+impl LdtkEntity for InteractibleBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let interactible = Interactible::new(entity_instance);
+        let size = IVec2::new(entity_instance.width, entity_instance.height).as_vec2();
+        InteractibleBundle {
+            sprite: Sprite::from_color(interactible.kind.color(false), size),
+            interactible,
+            collider: Collider::rectangle(size.x, size.y),
+            sensor: Sensor,
+            rigid_body: RigidBody::Static,
+            collision_layers: sensor_layers(true),
+        }
+    }
+}
+
+/// Doors are solid, they are never touched directly
+#[derive(Bundle)]
+pub struct DoorBundle {
+    interactible: Interactible,
+    sprite: Sprite,
+    collider: Collider,
+    rigid_body: RigidBody,
+    collision_layers: CollisionLayers,
+}
+
+impl LdtkEntity for DoorBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let interactible = Interactible::new(entity_instance);
+        let size = IVec2::new(entity_instance.width, entity_instance.height).as_vec2();
+        DoorBundle {
+            sprite: Sprite::from_color(interactible.kind.color(false), size),
+            interactible,
+            collider: Collider::rectangle(size.x, size.y),
+            rigid_body: RigidBody::Static,
+            collision_layers: door_layers(false),
+        }
+    }
+}
+
+fn sensor_layers(enabled: bool) -> CollisionLayers {
+    if enabled {
+        CollisionLayers::new(LayerEnum::Interactible, [LayerEnum::Player])
+    } else {
+        CollisionLayers::NONE
+    }
+}
+
+/// Closed doors are walls, so players can stand on them too
+fn door_layers(open: bool) -> CollisionLayers {
+    if open {
+        CollisionLayers::NONE
+    } else {
+        CollisionLayers::new(LayerEnum::Wall, [LayerEnum::Player])
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum InteractibleState {
+    Lever { on: bool },
+    Button { pressed_until: i32 },
+    Pickup { collected_by: usize },
+}
+
+/// What the players did to the interactibles of the current level, keyed by iid.
+///
+/// Level entities are spawned by every peer whenever its level finishes loading,
+/// so they can't be rolled back themselves. Their state lives here instead.
+#[derive(Resource, Clone, Default)]
+pub struct InteractibleStates(pub HashMap<String, InteractibleState>);
+
+impl InteractibleStates {
+    /// A lever that is on, a button still pressed or a pickup that got collected
+    pub fn is_active(&self, iid: &str, frame: i32) -> bool {
+        match self.0.get(iid) {
+            Some(InteractibleState::Lever { on }) => *on,
+            Some(InteractibleState::Button { pressed_until }) => frame < *pressed_until,
+            Some(InteractibleState::Pickup { .. }) => true,
+            None => false,
+        }
+    }
+
+    /// Doors open as long as any lever or button linked to them is active
+    fn is_door_open<'a>(
+        &self,
+        door: &str,
+        interactibles: impl IntoIterator<Item = &'a Interactible>,
+        frame: i32,
+    ) -> bool {
+        interactibles.into_iter().any(|interactible| {
+            interactible.door.as_deref() == Some(door) && self.is_active(&interactible.iid, frame)
+        })
+    }
+}
+
+/// Whether the player held use on the previous frame, holding it only triggers once
+#[derive(Component, Default, Clone)]
+pub struct UseHeld(pub bool);
+
+/// Uses the closest interactible each player is touching
+pub fn interact(
+    mut movement_events: EventReader<MovementEvent>,
+    mut states: ResMut<InteractibleStates>,
+    mut players: Query<(&Player, &Position, &CollidingEntities, &mut UseHeld), With<Rollback>>,
+    interactibles: Query<(&Interactible, &GlobalTransform)>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let using: Vec<usize> = movement_events
+        .read()
+        .flat_map(|event| &event.actions)
+        .filter_map(|action| match action {
+            MovementAction::Interact(handle) => Some(*handle),
+            _ => None,
+        })
+        .collect();
+    let frame: i32 = (*frame).into();
+
+    // When two players use the same thing on one frame the lower handle goes first
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(player, ..)| player.handle);
+
+    for (player, position, colliding, mut held) in players {
+        let pressed = using.contains(&player.handle);
+        let just_pressed = pressed && !held.0;
+        held.0 = pressed;
+        if !just_pressed {
+            continue;
+        }
+
+        let Some(interactible) = colliding
+            .iter()
+            .filter_map(|entity| interactibles.get(*entity).ok())
+            .min_by(|(_, a), (_, b)| {
+                let a = a.translation().truncate().distance_squared(position.0);
+                let b = b.translation().truncate().distance_squared(position.0);
+                a.total_cmp(&b)
+            })
+            .map(|(interactible, _)| interactible)
+        else {
+            continue;
+        };
+
+        let state = match interactible.kind {
+            InteractibleKind::Lever => InteractibleState::Lever {
+                on: !states.is_active(&interactible.iid, frame),
+            },
+            InteractibleKind::Button => InteractibleState::Button {
+                pressed_until: frame + BUTTON_FRAMES,
+            },
+            InteractibleKind::Pickup => {
+                if states.0.contains_key(&interactible.iid) {
+                    continue;
+                }
+                info!("Player {} collected {}", player.handle, interactible.iid);
+                InteractibleState::Pickup {
+                    collected_by: player.handle,
+                }
+            }
+            InteractibleKind::Door => continue,
+        };
+        states.0.insert(interactible.iid.clone(), state);
+    }
+}
+
+/// Opens and closes doors, and stops collected pickups from being touched
+pub fn update_interactible_colliders(
+    states: Res<InteractibleStates>,
+    mut interactibles: Query<(&Interactible, &mut CollisionLayers)>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let frame: i32 = (*frame).into();
+    let open_doors: Vec<String> = interactibles
+        .iter()
+        .filter(|(interactible, _)| interactible.kind == InteractibleKind::Door)
+        .filter(|(door, _)| {
+            states.is_door_open(&door.iid, interactibles.iter().map(|(i, _)| i), frame)
+        })
+        .map(|(door, _)| door.iid.clone())
+        .collect();
+
+    for (interactible, mut layers) in &mut interactibles {
+        let wanted = match interactible.kind {
+            InteractibleKind::Door => door_layers(open_doors.contains(&interactible.iid)),
+            InteractibleKind::Pickup => sensor_layers(!states.is_active(&interactible.iid, frame)),
+            InteractibleKind::Lever | InteractibleKind::Button => continue,
+        };
+        layers.set_if_neq(wanted);
+    }
+}
+
+pub fn show_interactibles(
+    states: Res<InteractibleStates>,
+    mut interactibles: Query<(&Interactible, &mut Sprite, &mut Visibility)>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let frame: i32 = (*frame).into();
+    let all: Vec<Interactible> = interactibles
+        .iter()
+        .map(|(interactible, ..)| interactible.clone())
+        .collect();
+
+    for (interactible, mut sprite, mut visibility) in &mut interactibles {
+        let active = match interactible.kind {
+            InteractibleKind::Door => states.is_door_open(&interactible.iid, &all, frame),
+            _ => states.is_active(&interactible.iid, frame),
+        };
+        sprite.color = interactible.kind.color(active);
+        if interactible.kind == InteractibleKind::Pickup {
+            visibility.set_if_neq(if active {
+                Visibility::Hidden
+            } else {
+                Visibility::Inherited
+            });
+        }
+    }
+}
+
+/// Levers, buttons, doors and pickups from the LDtk project
+pub struct InteractiblePlugin;
+
+impl Plugin for InteractiblePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InteractibleStates>()
+            .register_rollback_resource::<InteractibleStates>()
+            .register_rollback_component::<UseHeld>()
+            .add_systems(Update, show_interactibles);
+
+        for kind in InteractibleKind::ALL {
+            match kind {
+                InteractibleKind::Door => app.register_ldtk_entity::<DoorBundle>(kind.identifier()),
+                _ => app.register_ldtk_entity::<InteractibleBundle>(kind.identifier()),
+            };
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::Level, prelude::*, utils::ldtk_pixel_coords_to_translation_pivoted};
+use bevy_ggrs::{
+    ConfirmedFrameCount, PlayerInputs, Rollback, RollbackFrameCount, ggrs::InputStatus,
+};
+
+use crate::config::{EXIT_ENTITY, INPUT_LEVELS_READY, LEVEL_IIDS, MultiplayerConfig};
+
+use super::{
+    checkpoints::RespawnPoint,
+    death::Dead,
+    interactibles::InteractibleStates,
+    multiplayer::{READY_LAG, handshake::RollbackRegistryExt},
+    player::{Player, level_spawns, spawn_for_handle},
+    streaming::{StreamedLevels, is_streamed},
+    walls::WallGeometry,
+};
+
+/// Frames between touching the exit and appearing in the next level at the
+/// earliest. After that the players wait until every peer reports the new
+/// level is ready, so this is only a pause and not a loading time budget.
+const LEVEL_TRANSITION_FRAMES: i32 = 60;
+// Readiness reported earlier could be about the previous level
+const _: () = assert!(LEVEL_TRANSITION_FRAMES >= READY_LAG);
+
+const EXIT_COLOR: Color = Color::srgba(1.0, 0.85, 0.2, 0.6);
+
+/// Which of `LEVEL_IIDS` is being played, rolled back with the rest of the simulation
+#[derive(Resource, Clone, Default, Debug)]
+pub struct LevelProgress {
+    pub index: usize,
+    /// Frame `index` last changed on
+    pub changed_at_frame: i32,
+    /// Players get placed into the current level on this frame
+    pub respawn_at_frame: Option<i32>,
+    /// Last `LevelRevision` the simulation reacted to
+    pub revision: u32,
+}
+
+impl LevelProgress {
+    pub fn level_iid(&self) -> &'static str {
+        LEVEL_IIDS[self.index]
+    }
+}
+
+/// Whether the level the simulation is in and the levels it streams in are
+/// spawned here with their colliders, sent to the other peers with our inputs
+/// as `INPUT_LEVELS_READY`
+#[derive(Resource, Default, PartialEq)]
+pub struct LevelsReady(pub bool);
+
+/// Whether every peer had the levels it simulates loaded when it sent its
+/// inputs for this frame. Part of the inputs, so every peer agrees on it.
+pub fn all_levels_ready(inputs: &PlayerInputs<MultiplayerConfig>) -> bool {
+    inputs.iter().all(|(input, status)| {
+        // Players that left can't hold the others up
+        matches!(status, InputStatus::Disconnected) || input & INPUT_LEVELS_READY != 0
+    })
+}
+
+/// Counts the reloads of the LDtk project from disk while developing
+#[derive(Resource, Default)]
+pub struct LevelRevision(pub u32);
+
+/// Rollback entities that belong to the current level and go away with it
+#[derive(Component, Default, Clone)]
+pub struct LevelScoped;
+
+#[derive(Component, Default)]
+pub struct LevelExit;
+
+#[derive(Bundle, Default)]
+pub struct LevelExitBundle {
+    exit: LevelExit,
+    sprite: Sprite,
+}
+
+impl LdtkEntity for LevelExitBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        _: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let size = IVec2::new(entity_instance.width, entity_instance.height).as_vec2();
+        LevelExitBundle {
+            exit: LevelExit,
+            sprite: Sprite::from_color(EXIT_COLOR, size),
+        }
+    }
+}
+
+/// Looks a level up in the project by its iid
+pub fn find_level<'a>(project: &'a LdtkProject, iid: &str) -> Option<&'a Level> {
+    project
+        .json_data()
+        .levels
+        .iter()
+        .find(|level| level.iid == iid)
+}
+
+/// World space bounds of a level
+pub fn level_bounds(level: &Level) -> Rect {
+    let min = Vec2::new(level.world_x as f32, -(level.world_y + level.px_hei) as f32);
+    Rect::from_corners(min, min + IVec2::new(level.px_wid, level.px_hei).as_vec2())
+}
+
+/// Lowest point of the world below `x`, the bottom of the current level when
+/// playing levels one after another and of the lowest level spanning `x` in a
+/// streamed world
+pub fn world_bottom(project: &LdtkProject, progress: &LevelProgress, x: f32) -> Option<f32> {
+    if !is_streamed(project) {
+        return find_level(project, progress.level_iid()).map(|level| level_bounds(level).min.y);
+    }
+
+    let levels = &project.json_data().levels;
+    let bottoms = |spanning_x: bool| {
+        levels
+            .iter()
+            .map(level_bounds)
+            .filter(move |bounds| !spanning_x || (bounds.min.x..=bounds.max.x).contains(&x))
+            .map(|bounds| bounds.min.y)
+            .min_by(f32::total_cmp)
+    };
+    // Past the sides of the world the lowest level counts
+    bottoms(true).or_else(|| bottoms(false))
+}
+
+/// Every instance of an entity in a level, with its world space bounds.
+///
+/// Read straight from the project, so it doesn't depend on
+/// when the level entities finished spawning on this peer.
+pub fn level_entities<'a>(
+    level: &'a Level,
+    identifier: &'a str,
+) -> impl Iterator<Item = (&'a EntityInstance, Rect)> {
+    let level_offset = level_bounds(level).min;
+
+    level
+        .layer_instances
+        .iter()
+        .flatten()
+        .flat_map(move |layer| {
+            layer
+                .entity_instances
+                .iter()
+                .filter(move |entity| entity.identifier == identifier)
+                .map(move |entity| {
+                    let size = IVec2::new(entity.width, entity.height);
+                    let center = ldtk_pixel_coords_to_translation_pivoted(
+                        entity.px,
+                        layer.c_hei * layer.grid_size,
+                        size,
+                        entity.pivot,
+                    );
+                    (
+                        entity,
+                        Rect::from_center_size(center + level_offset, size.as_vec2()),
+                    )
+                })
+        })
+}
+
+/// World space bounds of every instance of an entity in a level
+pub fn level_entity_rects(level: &Level, identifier: &str) -> Vec<Rect> {
+    level_entities(level, identifier)
+        .map(|(_, rect)| rect)
+        .collect()
+}
+
+/// Starts the transition to the next level once any player reaches an exit
+pub fn check_level_exit(
+    mut commands: Commands,
+    mut progress: ResMut<LevelProgress>,
+    players: Query<&Position, (With<Player>, With<Rollback>)>,
+    level_scoped: Query<Entity, With<LevelScoped>>,
+    frame: Res<RollbackFrameCount>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    if progress.respawn_at_frame.is_some() {
+        return;
+    }
+    let Some(level) = ldtk_project_assets
+        .get(ldtk_projects.single())
+        .and_then(|project| find_level(project, progress.level_iid()))
+    else {
+        return;
+    };
+
+    let exits = level_entity_rects(level, EXIT_ENTITY);
+    let reached = players
+        .iter()
+        .any(|position| exits.iter().any(|exit| exit.contains(position.0)));
+    if !reached {
+        return;
+    }
+
+    let frame: i32 = (*frame).into();
+    // After the last level we start over
+    progress.index = (progress.index + 1) % LEVEL_IIDS.len();
+    progress.changed_at_frame = frame;
+    progress.respawn_at_frame = Some(frame + LEVEL_TRANSITION_FRAMES);
+    info!(
+        "Exit reached on frame {}, moving to level {}",
+        frame,
+        progress.level_iid()
+    );
+
+    for entity in &level_scoped {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Restarts the current level after the LDtk project got reloaded,
+/// once ldtk has respawned it and its colliders were rebuilt.
+///
+/// Only runs in SyncTest sessions, which have no other peer to agree with on when
+/// to switch to the new levels. Networked matches end on a reload instead, see
+/// `end_match_on_level_reload`.
+pub fn restart_reloaded_level(
+    mut progress: ResMut<LevelProgress>,
+    revision: Res<LevelRevision>,
+    frame: Res<RollbackFrameCount>,
+) {
+    if progress.revision == revision.0 || progress.respawn_at_frame.is_some() {
+        return;
+    }
+    let frame: i32 = (*frame).into();
+    info!(
+        "Level reloaded, restarting it on frame {}",
+        frame + LEVEL_TRANSITION_FRAMES
+    );
+    progress.revision = revision.0;
+    progress.respawn_at_frame = Some(frame + LEVEL_TRANSITION_FRAMES);
+}
+
+/// Holds the players still during a transition, then places them on the spawn
+/// points of the new level once it is loaded on every peer
+#[allow(clippy::too_many_arguments)]
+pub fn enter_next_level(
+    mut commands: Commands,
+    mut progress: ResMut<LevelProgress>,
+    mut interactibles: ResMut<InteractibleStates>,
+    mut players: Query<
+        (
+            Entity,
+            &Player,
+            &mut Position,
+            &mut LinearVelocity,
+            &mut RespawnPoint,
+        ),
+        With<Rollback>,
+    >,
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<MultiplayerConfig>>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let Some(respawn_at_frame) = progress.respawn_at_frame else {
+        return;
+    };
+
+    for (_, _, _, mut velocity, _) in &mut players {
+        velocity.0 = Vec2::ZERO;
+    }
+
+    let frame: i32 = (*frame).into();
+    if frame < respawn_at_frame || !all_levels_ready(&inputs) {
+        return;
+    }
+
+    let Some(level) = ldtk_project_assets
+        .get(ldtk_projects.single())
+        .and_then(|project| find_level(project, progress.level_iid()))
+    else {
+        error!("Level {} is not in the project", progress.level_iid());
+        return;
+    };
+    let spawns = level_spawns(level);
+    if spawns.is_empty() {
+        error!("Level {} has no spawn points", progress.level_iid());
+        return;
+    }
+
+    // Handles instead of query order, which can differ between peers
+    for (entity, player, mut position, _, mut respawn) in &mut players {
+        let Some(spawn) = spawn_for_handle(&spawns, player.handle) else {
+            continue;
+        };
+        position.0 = spawn;
+        // Checkpoints of the previous level are gone
+        respawn.0 = None;
+        commands.entity(entity).remove::<Dead>();
+    }
+    interactibles.0.clear();
+    progress.respawn_at_frame = None;
+}
+
+pub fn track_level_reloads(
+    mut events: EventReader<AssetEvent<LdtkProject>>,
+    mut revision: ResMut<LevelRevision>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified { .. } = event {
+            info!("LDtk project changed on disk");
+            revision.0 += 1;
+        }
+    }
+}
+
+/// Loads the level the simulation is in, streamed worlds take care of that themselves.
+///
+/// A predicted exit can still be rolled back, so the level only changes once
+/// the frame it changed on is confirmed by every peer.
+pub fn sync_level_selection(
+    progress: Res<LevelProgress>,
+    confirmed_frame: Res<ConfirmedFrameCount>,
+    level_selection: Option<ResMut<LevelSelection>>,
+) {
+    let Some(mut level_selection) = level_selection else {
+        return;
+    };
+    let confirmed_frame: i32 = (*confirmed_frame).into();
+    if confirmed_frame < progress.changed_at_frame {
+        return;
+    }
+    let iid = LevelIid::new(progress.level_iid());
+    if !matches!(&*level_selection, LevelSelection::Iid(current) if *current == iid) {
+        info!("Loading level {}", iid);
+        *level_selection = LevelSelection::Iid(iid);
+    }
+}
+
+/// Checks whether the levels the simulation is in are spawned with their walls,
+/// `spawn_wall_collision` only fills in `WallGeometry` once it built them
+pub fn check_levels_ready(
+    mut ready: ResMut<LevelsReady>,
+    progress: Res<LevelProgress>,
+    streamed: Res<StreamedLevels>,
+    levels: Query<(Entity, &LevelIid)>,
+    wall_geometry: Res<WallGeometry>,
+) {
+    let spawned = |wanted: &str| {
+        levels
+            .iter()
+            .any(|(entity, iid)| iid.as_str() == wanted && wall_geometry.0.contains_key(&entity))
+    };
+    let all_spawned = spawned(progress.level_iid()) && streamed.0.keys().all(|iid| spawned(iid));
+    ready.set_if_neq(LevelsReady(all_spawned));
+}
+
+/// Moves the players through `LEVEL_IIDS` as they reach the exits
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LevelProgress>()
+            .init_resource::<LevelRevision>()
+            .init_resource::<LevelsReady>()
+            .register_rollback_resource::<LevelProgress>()
+            .register_ldtk_entity::<LevelExitBundle>(EXIT_ENTITY)
+            .add_systems(
+                Update,
+                (
+                    sync_level_selection.run_if(resource_exists::<ConfirmedFrameCount>),
+                    track_level_reloads,
+                    check_levels_ready,
+                ),
+            );
+    }
+}
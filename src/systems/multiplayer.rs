@@ -1,102 +1,181 @@
 use avian2d::prelude::*;
 use bevy::{prelude::*, utils::HashMap};
+use bevy_egui::EguiContexts;
 use bevy_ggrs::{
     AddRollbackCommandExtension, LocalInputs, LocalPlayers,
     ggrs::{self},
     prelude::*,
 };
-use bevy_matchbox::MatchboxSocket;
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+use connection::{
+    ConnectTimeout, ConnectionFailure, connecting_screen, connection_error_window, disconnect,
+    handle_socket_closed, wait_for_signaling,
+};
+use handshake::{
+    PeerVersions, ProtocolVersion, RollbackRegistryExt, all_peers_verified,
+    compute_protocol_version, exchange_versions,
+};
+use identity::{LocalIdentity, PeerIdentities, PlayerIdentity, PlayerTag, exchange_identities};
+use lobby::{LobbyReadiness, MatchSettings, lobby_host, lobby_window, track_lobby};
+use messages::{
+    PeerMessageEvent, PeerPings, PeerStateEvent, PeerVersionEvent, SocketClosed, answer_pings,
+    receive_peer_messages, send_pings,
+};
+use room::{MatchMode, match_menu};
+use session::{
+    RematchRequests, end_match_on_level_reload, handle_session_events, pause_menu,
+    receive_match_messages, results_screen, start_rematch, teardown_session, toggle_pause,
+};
+use synctest::start_synctest_session;
 
 use crate::{
     config::*,
     systems::{
+        bots::{Bot, BotInputs},
         controller::CharacterControllerBundle,
         player::{Player, PlayerBundle},
     },
 };
 use crate::{
-    game::GameState,
-    systems::{colliders::CharacterCollider, player::SpawnPlayerEvent},
+    game::{GameState, PauseState},
+    systems::{GameAssets, colliders::CharacterCollider, death::Dead, levels::LevelsReady},
 };
 
-use super::controller::Grounded;
+use super::controller::{GroundSurface, GroundVelocity, Grounded, OnOneWayPlatform};
+
+pub mod connection;
+pub mod handshake;
+pub mod identity;
+pub mod lobby;
+pub mod messages;
+pub mod room;
+pub mod session;
+pub mod synctest;
+
+pub const TARGET_FPS: usize = 60;
+/// Frames our inputs are held back before they get simulated
+const INPUT_DELAY: usize = 1;
+/// How far a peer gets to predict ahead of the last frame it has every input for
+const MAX_PREDICTION: usize = 12;
+/// Frames after something happened in the simulation before the inputs of every
+/// peer are sure to come from a simulation where it happened too.
+///
+/// Inputs are read the frame before they are delayed by `INPUT_DELAY`, and the
+/// simulation they are read next to may still be mispredicted `MAX_PREDICTION`
+/// frames back. Not a guess at how fast anything loads, GGRS holds peers to it.
+pub const READY_LAG: i32 = (INPUT_DELAY + MAX_PREDICTION) as i32 + 2;
+
+/// Session frame the players appear on at the earliest. Like every respawn
+/// they wait until every peer reports the level loaded in its inputs.
+const SPAWN_FRAME: i32 = 60;
+/// Where players wait for `SPAWN_FRAME`
+const PARKING_Y: f32 = -100_000.;
+const PARKING_SPACING: f32 = 32.;
 
-const TARGET_FPS: usize = 60;
+/// Tells bots apart from the humans using the same sprites
+const BOT_TINT: Color = Color::srgb(0.6, 0.7, 1.0);
+
+/// Unreliable channel handed over to GGRS
+pub const GGRS_CHANNEL: usize = 0;
+/// Reliable channel for everything that must not touch the simulation
+pub const RELIABLE_CHANNEL: usize = 1;
 
 #[derive(Component)]
 pub struct Local;
 
-pub fn start_matchbox_socket(mut commands: Commands) {
-    // wasm_test -> scope
-    // next=2 -> make room connect pairs as they connect
-    let room_url = "ws://127.0.0.1:3536/wasm_test?next=2";
-    info!("Connecting to matchbox server");
-    commands.insert_resource(MatchboxSocket::new_unreliable(room_url));
+pub fn start_matchbox_socket(mut commands: Commands, match_mode: Res<MatchMode>) {
+    let Some(room_url) = match_mode.room_url() else {
+        return;
+    };
+    info!("Connecting to matchbox server at {}", room_url);
+    let socket = WebRtcSocketBuilder::new(room_url)
+        .add_channel(ChannelConfig::unreliable())
+        .add_channel(ChannelConfig::reliable());
+    commands.insert_resource(MatchboxSocket::from(socket));
+    commands.insert_resource(PeerVersions::default());
+    commands.insert_resource(LobbyReadiness::default());
+    commands.insert_resource(MatchSettings::default());
+    commands.insert_resource(PeerPings::default());
+    commands.insert_resource(PeerIdentities::default());
+    commands.insert_resource(ConnectTimeout::default());
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn wait_for_payers(
     mut commands: Commands,
     mut socket: ResMut<MatchboxSocket>,
-    mut spawned_event: EventWriter<SpawnPlayerEvent>,
-    asset_server: Res<AssetServer>,
+    game_assets: Res<GameAssets>,
+    peer_versions: Res<PeerVersions>,
+    version: Res<ProtocolVersion>,
+    readiness: Res<LobbyReadiness>,
+    settings: Res<MatchSettings>,
+    local_identity: Res<LocalIdentity>,
+    identities: Res<PeerIdentities>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if socket.get_channel(0).is_err() {
+    if socket.get_channel(GGRS_CHANNEL).is_err() {
+        return;
+    }
+    // Until the signaling server answers we don't know who we are
+    let Some(our_id) = socket.id() else {
+        return;
+    };
+    let mut players = socket.players();
+    let num_players = NUM_PLAYERS;
+    let humans = settings.humans();
+    if players.len() != humans {
         return;
     }
-    socket.update_peers();
-    let players = socket.players();
-    let num_players = 2;
-    if players.len() < num_players {
+    // Peers from a different build would desync on the first frame
+    if !all_peers_verified(&socket, &peer_versions, &version) {
+        return;
+    }
+    if !readiness.all_ready(&socket) {
+        return;
+    }
+    if !identities.all_known(&socket) {
         return;
     }
     info!("All peers have joined, starting game");
 
+    // Bots take the handles after the humans and are simulated by the host
+    let host = lobby_host(&mut socket).unwrap_or(our_id);
+    let bot_owner = if host == our_id {
+        PlayerType::Local
+    } else {
+        PlayerType::Remote(host)
+    };
+    players.extend(std::iter::repeat(bot_owner).take(settings.bots));
+
     // create a GGRS P2P session
     let mut session_builder = ggrs::SessionBuilder::<MultiplayerConfig>::new()
         .with_num_players(num_players)
-        .with_input_delay(1)
+        .with_input_delay(INPUT_DELAY)
         .with_fps(TARGET_FPS)
         .unwrap()
-        .with_max_prediction_window(12)
+        .with_max_prediction_window(MAX_PREDICTION)
         // If Saving game state >>> advancing game state
         .with_sparse_saving_mode(false)
         .with_desync_detection_mode(ggrs::DesyncDetection::On { interval: 1 });
 
-    // Add local player handles - for simplicity, assume player 0 is local
-    let mut local_player_handles = Vec::new();
-
-    for (i, player) in players.into_iter().enumerate() {
+    for (i, player) in players.iter().enumerate() {
         session_builder = session_builder
-            .add_player(player, i)
+            .add_player(*player, i)
             .expect("failed to add player");
-        info!("Created player");
-        let texture = match player {
-            PlayerType::Local => {
-                local_player_handles.push(i);
-                asset_server.load("atlas/kornel.png")
-            }
-            _ => asset_server.load("atlas/wera.png"),
-        };
-        let sprite_sheet = Sprite::from_image(texture);
-
-        let mut player_c = commands.spawn(PlayerBundle {
-            player: Player { handle: i },
-            sprite_sheet,
-            character_controller: CharacterControllerBundle::from(CharacterCollider::Player),
-            ..Default::default()
-        });
-        if player == PlayerType::Local {
-            player_c.insert(Local);
-        }
-
-        spawned_event.send(SpawnPlayerEvent(player_c.id()));
-        player_c.add_rollback();
     }
+    let local_player_handles = spawn_match_players(
+        &mut commands,
+        &game_assets,
+        &players,
+        humans,
+        &local_identity.0,
+        &identities,
+    );
 
     // Add resource for local players
     commands.insert_resource(LocalPlayers(local_player_handles));
     // move the channel out of the socket (required because GGRS takes ownership of it)
-    let channel = socket.take_channel(0).unwrap();
+    let channel = socket.take_channel(GGRS_CHANNEL).unwrap();
 
     // start the GGRS session
     let ggrs_session = session_builder
@@ -104,17 +183,112 @@ pub fn wait_for_payers(
         .expect("failed to start session");
 
     commands.insert_resource(bevy_ggrs::Session::P2P(ggrs_session));
+    next_state.set(GameState::InGame);
 }
 
+/// Spawns the rollback entities of the players of a session, handles from `humans` on are bots.
+/// Returns the handles of the local players.
+///
+/// Every player is tagged with the name and color of whoever controls it, `local` for ours.
+///
+/// Players start out dead outside the level, the simulation brings them in on
+/// `SPAWN_FRAME` so every peer places them on the same frame from the same state.
+pub fn spawn_match_players(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    players: &[PlayerType<PeerId>],
+    humans: usize,
+    local: &PlayerIdentity,
+    identities: &PeerIdentities,
+) -> Vec<usize> {
+    let mut local_player_handles = Vec::new();
+
+    for (i, player) in players.iter().enumerate() {
+        info!("Created player");
+        let is_bot = i >= humans;
+        if *player == PlayerType::Local {
+            local_player_handles.push(i);
+        }
+        let texture = if *player == PlayerType::Local && !is_bot {
+            game_assets.local_player.clone()
+        } else {
+            game_assets.remote_player.clone()
+        };
+        let mut sprite_sheet = Sprite::from_image(texture);
+        if is_bot {
+            sprite_sheet.color = BOT_TINT;
+        }
+        let peer = match player {
+            PlayerType::Remote(peer) => Some(*peer),
+            _ => None,
+        };
+        let identity = if is_bot {
+            PlayerIdentity::bot(i - humans + 1)
+        } else if let Some(peer) = peer {
+            identities.identity(peer)
+        } else {
+            local.clone()
+        };
+
+        // Apart from each other and far from any level, until they spawn
+        let parked = Vec2::new(i as f32 * PARKING_SPACING, PARKING_Y);
+        let mut player_c = commands.spawn((
+            PlayerBundle {
+                player: Player { handle: i },
+                sprite_sheet,
+                character_controller: CharacterControllerBundle::from(CharacterCollider::Player),
+                transform: Transform::from_translation(parked.extend(PLAYER_Z))
+                    .with_scale(Vec3::splat(0.5)),
+                ..Default::default()
+            },
+            Position(parked),
+            Dead {
+                respawn_at_frame: SPAWN_FRAME,
+            },
+            // Only shown, never read by the simulation
+            PlayerTag { identity, peer },
+        ));
+        if is_bot {
+            player_c.insert(Bot);
+        } else if *player == PlayerType::Local {
+            player_c.insert(Local);
+        }
+
+        player_c.add_rollback();
+    }
+
+    local_player_handles
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn read_local_inputs(
     mut commands: Commands,
     keys: Res<ButtonInput<KeyCode>>,
     local_players: Res<LocalPlayers>,
+    bot_inputs: Res<BotInputs>,
+    bots: Query<&Player, With<Bot>>,
+    levels_ready: Res<LevelsReady>,
+    mut contexts: EguiContexts,
+    pause_state: Option<Res<State<PauseState>>>,
 ) {
     let mut local_inputs = HashMap::new();
+    // Typing in the chat or sitting in the pause menu shouldn't move the player
+    let typing = contexts.ctx_mut().wants_keyboard_input()
+        || pause_state.is_some_and(|state| *state.get() == PauseState::Paused);
 
     for handle in &local_players.0 {
+        // A bot that didn't decide yet stands still instead of following our keyboard
+        if bots.iter().any(|bot| bot.handle == *handle) {
+            let input = bot_inputs.0.get(handle).copied().unwrap_or(0);
+            local_inputs.insert(*handle, input);
+            continue;
+        }
+
         let mut input = 0u8;
+        if typing {
+            local_inputs.insert(*handle, input);
+            continue;
+        }
 
         if keys.any_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
             input |= INPUT_UP;
@@ -138,6 +312,13 @@ pub fn read_local_inputs(
         local_inputs.insert(*handle, input);
     }
 
+    // Bots are simulated here, so they go by our levels too
+    if levels_ready.0 {
+        for input in local_inputs.values_mut() {
+            *input |= INPUT_LEVELS_READY;
+        }
+    }
+
     commands.insert_resource(LocalInputs::<MultiplayerConfig>(local_inputs));
 }
 
@@ -146,22 +327,25 @@ impl Plugin for MultiplayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(GgrsPlugin::<MultiplayerConfig>::default())
             // Location
-            .rollback_component_with_clone::<Transform>()
-            .rollback_component_with_clone::<GlobalTransform>()
+            .register_rollback_component::<Transform>()
+            .register_rollback_component::<GlobalTransform>()
             // Physics
-            .rollback_component_with_clone::<LinearVelocity>()
-            .rollback_component_with_clone::<Position>()
-            .rollback_component_with_clone::<ShapeHits>()
-            .rollback_component_with_clone::<CollidingEntities>()
-            .rollback_component_with_clone::<Rotation>()
-            .rollback_resource_with_clone::<Collisions>()
+            .register_rollback_component::<LinearVelocity>()
+            .register_rollback_component::<Position>()
+            .register_rollback_component::<ShapeHits>()
+            .register_rollback_component::<CollidingEntities>()
+            .register_rollback_component::<Rotation>()
+            .register_rollback_resource::<Collisions>()
             //Time
-            .rollback_resource_with_clone::<Time<Physics>>()
-            // .rollback_resource_with_clone::<Time>()
-            .rollback_component_with_clone::<TimeSleeping>()
-            .rollback_component_with_clone::<Sleeping>()
+            .register_rollback_resource::<Time<Physics>>()
+            // .register_rollback_resource::<Time>()
+            .register_rollback_component::<TimeSleeping>()
+            .register_rollback_component::<Sleeping>()
             //Custom
-            .rollback_component_with_clone::<Grounded>()
+            .register_rollback_component::<Grounded>()
+            .register_rollback_component::<GroundSurface>()
+            .register_rollback_component::<GroundVelocity>()
+            .register_rollback_component::<OnOneWayPlatform>()
             .checksum_component::<Position>(|position| {
                 let mut bytes: Vec<u8> = Vec::new();
                 bytes.extend(position.x.to_ne_bytes());
@@ -181,11 +365,78 @@ impl Plugin for MultiplayerPlugin {
                 fletcher16(&bytes) as u64
             })
             .set_rollback_schedule_fps(TARGET_FPS)
-            .add_systems(Startup, start_matchbox_socket)
             .add_systems(ReadInputs, read_local_inputs)
+            .add_event::<PeerMessageEvent>()
+            .add_event::<PeerStateEvent>()
+            .add_event::<PeerVersionEvent>()
+            .init_resource::<RematchRequests>()
+            .init_resource::<PeerPings>()
+            .init_resource::<MatchSettings>()
+            .init_resource::<LocalIdentity>()
+            .add_event::<SocketClosed>()
+            .add_systems(OnEnter(GameState::MainMenu), disconnect)
+            .add_systems(
+                OnEnter(GameState::Connecting),
+                (compute_protocol_version, start_matchbox_socket),
+            )
+            .add_systems(OnEnter(GameState::InGame), start_synctest_session)
+            .add_systems(OnExit(GameState::InGame), teardown_session)
+            .add_systems(
+                PreUpdate,
+                receive_peer_messages.run_if(resource_exists::<MatchboxSocket>),
+            )
             .add_systems(
                 Update,
-                (wait_for_payers.run_if(in_state(GameState::Playing)),),
+                (
+                    // Main menu
+                    (
+                        match_menu,
+                        connection_error_window.run_if(resource_exists::<ConnectionFailure>),
+                    )
+                        .run_if(in_state(GameState::MainMenu)),
+                    // Every state with a socket
+                    (
+                        send_pings,
+                        answer_pings,
+                        exchange_identities,
+                        handle_socket_closed.run_if(resource_exists::<MatchMode>),
+                    )
+                        .run_if(resource_exists::<MatchboxSocket>),
+                    // Connecting and lobby, peers can show up as soon as we have an id
+                    (exchange_versions, track_lobby)
+                        .distributive_run_if(resource_exists::<MatchboxSocket>)
+                        .run_if(in_state(GameState::Connecting).or(in_state(GameState::Lobby))),
+                    (wait_for_signaling, connecting_screen)
+                        .distributive_run_if(resource_exists::<MatchboxSocket>)
+                        .run_if(in_state(GameState::Connecting)),
+                    (lobby_window, wait_for_payers)
+                        .chain()
+                        .after(exchange_versions)
+                        .after(track_lobby)
+                        .distributive_run_if(resource_exists::<MatchboxSocket>)
+                        .run_if(in_state(GameState::Lobby)),
+                    // In game
+                    (
+                        handle_session_events.run_if(resource_exists::<Session<MultiplayerConfig>>),
+                        end_match_on_level_reload.run_if(
+                            resource_exists::<Session<MultiplayerConfig>>
+                                .and(resource_exists::<MatchboxSocket>),
+                        ),
+                        toggle_pause,
+                    )
+                        .run_if(in_state(GameState::InGame)),
+                    pause_menu.run_if(in_state(PauseState::Paused)),
+                    receive_match_messages.run_if(
+                        resource_exists::<MatchboxSocket>
+                            .and(in_state(GameState::InGame).or(in_state(GameState::Results))),
+                    ),
+                    (
+                        results_screen,
+                        start_rematch.run_if(resource_exists::<MatchboxSocket>),
+                    )
+                        .chain()
+                        .run_if(in_state(GameState::Results)),
+                ),
             );
     }
 }
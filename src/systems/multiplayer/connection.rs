@@ -0,0 +1,204 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+
+use crate::game::GameState;
+
+use super::{
+    handshake::PeerVersions,
+    identity::PeerIdentities,
+    lobby::{LobbyReadiness, MatchSettings},
+    messages::{PeerPings, SocketClosed},
+    room::MatchMode,
+    session::MatchEnd,
+};
+
+/// How long the matchmaking server gets to let us in
+const CONNECT_TIMEOUT_SECS: f32 = 10.;
+
+/// Why we are back in the main menu instead of playing
+#[derive(Debug, Clone)]
+pub enum ConnectionError {
+    /// The matchmaking server couldn't be reached
+    Unreachable,
+    /// The matchmaking server didn't answer in time
+    Timeout,
+    /// The connection dropped while in the lobby
+    Lost,
+    /// A peer runs a different build, playing together would desync
+    VersionMismatch {
+        peer: PeerId,
+        ours: u64,
+        theirs: u64,
+    },
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Unreachable => f.write_str("The matchmaking server can't be reached."),
+            ConnectionError::Timeout => f.write_str("The matchmaking server didn't answer."),
+            ConnectionError::Lost => f.write_str("The connection to the lobby was lost."),
+            ConnectionError::VersionMismatch { peer, ours, theirs } => write!(
+                f,
+                "Peer {} is running a different build of the game.\n\
+                 Ours: {:016x}, theirs: {:016x}\n\
+                 Both players need to run the same version to play together.",
+                peer, ours, theirs
+            ),
+        }
+    }
+}
+
+/// The last connection attempt failed, shown in the main menu until dismissed
+#[derive(Resource, Debug, Clone)]
+pub struct ConnectionFailure {
+    pub error: ConnectionError,
+    /// What we tried to connect to, for retrying
+    pub mode: MatchMode,
+}
+
+/// Time left for the matchmaking server to answer
+#[derive(Resource)]
+pub struct ConnectTimeout(Timer);
+
+impl Default for ConnectTimeout {
+    fn default() -> Self {
+        ConnectTimeout(Timer::from_seconds(CONNECT_TIMEOUT_SECS, TimerMode::Once))
+    }
+}
+
+/// Gives up on connecting, back to the main menu with `error` to show
+pub fn fail_connection(
+    commands: &mut Commands,
+    next_state: &mut NextState<GameState>,
+    mode: &MatchMode,
+    error: ConnectionError,
+) {
+    warn!("Connection failed: {:?}", error);
+    commands.insert_resource(ConnectionFailure {
+        error,
+        mode: mode.clone(),
+    });
+    next_state.set(GameState::MainMenu);
+}
+
+/// Enters the lobby once the matchmaking server told us who we are
+pub fn wait_for_signaling(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket>,
+    mut timeout: ResMut<ConnectTimeout>,
+    match_mode: Res<MatchMode>,
+    time: Res<Time<Real>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if socket.id().is_some() {
+        info!("Connected, entering lobby");
+        next_state.set(GameState::Lobby);
+    } else if timeout.0.tick(time.delta()).just_finished() {
+        fail_connection(
+            &mut commands,
+            &mut next_state,
+            &match_mode,
+            ConnectionError::Timeout,
+        );
+    }
+}
+
+pub fn connecting_screen(
+    mut contexts: EguiContexts,
+    match_mode: Res<MatchMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    egui::Window::new("Connecting")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(match &*match_mode {
+                    MatchMode::Private(code) => format!("Joining room {}...", code),
+                    _ => "Looking for a match...".to_string(),
+                });
+            });
+            if ui.button("Cancel").clicked() {
+                next_state.set(GameState::MainMenu);
+            }
+        });
+}
+
+/// Decides what a dead socket means for where we are
+pub fn handle_socket_closed(
+    mut commands: Commands,
+    mut closed: EventReader<SocketClosed>,
+    match_mode: Res<MatchMode>,
+    game_state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if closed.read().last().is_none() {
+        return;
+    }
+    // Nothing can be received anymore, keeping it only repeats the error
+    commands.remove_resource::<MatchboxSocket>();
+    match game_state.get() {
+        GameState::Connecting => fail_connection(
+            &mut commands,
+            &mut next_state,
+            &match_mode,
+            ConnectionError::Unreachable,
+        ),
+        GameState::Lobby => fail_connection(
+            &mut commands,
+            &mut next_state,
+            &match_mode,
+            ConnectionError::Lost,
+        ),
+        GameState::InGame => {
+            commands.insert_resource(MatchEnd::Disconnected);
+            next_state.set(GameState::Results);
+        }
+        _ => {}
+    }
+}
+
+pub fn connection_error_window(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    failure: Res<ConnectionFailure>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    egui::Window::new("Couldn't connect")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0., 16.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(failure.error.to_string());
+            ui.horizontal(|ui| {
+                // Another try won't fix a different build
+                let retryable = !matches!(failure.error, ConnectionError::VersionMismatch { .. });
+                if retryable && ui.button("Retry").clicked() {
+                    commands.insert_resource(failure.mode.clone());
+                    commands.remove_resource::<ConnectionFailure>();
+                    next_state.set(GameState::Connecting);
+                }
+                if ui.button("OK").clicked() {
+                    commands.remove_resource::<ConnectionFailure>();
+                }
+            });
+        });
+}
+
+/// Closes the socket and forgets the room, nothing is connected in the main menu
+pub fn disconnect(mut commands: Commands) {
+    commands.remove_resource::<MatchboxSocket>();
+    commands.remove_resource::<MatchMode>();
+    commands.remove_resource::<ConnectTimeout>();
+    commands.remove_resource::<PeerVersions>();
+    commands.remove_resource::<LobbyReadiness>();
+    commands.remove_resource::<PeerIdentities>();
+    commands.insert_resource(PeerPings::default());
+    commands.insert_resource(MatchSettings::default());
+}
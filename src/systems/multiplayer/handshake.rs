@@ -0,0 +1,202 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_ggrs::GgrsApp;
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+
+use crate::{config::*, game::GameState};
+
+use super::{
+    connection::{ConnectionError, fail_connection},
+    messages::{PeerStateEvent, PeerVersionEvent, send_version},
+    room::MatchMode,
+};
+
+/// Hash of everything two peers have to agree on before simulating together
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ProtocolVersion(pub u64);
+
+/// Versions announced by the connected peers
+#[derive(Resource, Default)]
+pub struct PeerVersions(pub HashMap<PeerId, u64>);
+
+/// Rollback registrations in the order they were made,
+/// so they can be part of the protocol version
+#[derive(Resource, Default)]
+pub struct RollbackRegistry(Vec<&'static str>);
+
+pub trait RollbackRegistryExt {
+    fn register_rollback_component<T: Component + Clone>(&mut self) -> &mut Self;
+    fn register_rollback_resource<T: Resource + Clone>(&mut self) -> &mut Self;
+}
+
+impl RollbackRegistryExt for App {
+    fn register_rollback_component<T: Component + Clone>(&mut self) -> &mut Self {
+        self.init_resource::<RollbackRegistry>();
+        self.world_mut()
+            .resource_mut::<RollbackRegistry>()
+            .0
+            .push(std::any::type_name::<T>());
+        self.rollback_component_with_clone::<T>()
+    }
+
+    fn register_rollback_resource<T: Resource + Clone>(&mut self) -> &mut Self {
+        self.init_resource::<RollbackRegistry>();
+        self.world_mut()
+            .resource_mut::<RollbackRegistry>()
+            .0
+            .push(std::any::type_name::<T>());
+        self.rollback_resource_with_clone::<T>()
+    }
+}
+
+/// FNV-1a, unlike `DefaultHasher` it is guaranteed to be the same on every build
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_str(&mut self, s: &str) {
+        self.write_i32(s.len() as i32);
+        self.write(s.as_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+/// Checksum of the parts of the LDtk project that end up in the simulation
+fn hash_ldtk_project(hasher: &mut StableHasher, project: &LdtkProject) {
+    for level in &project.json_data().levels {
+        hasher.write_str(&level.iid);
+        hasher.write_i32(level.world_x);
+        hasher.write_i32(level.world_y);
+        hasher.write_i32(level.px_wid);
+        hasher.write_i32(level.px_hei);
+
+        for layer in level.layer_instances.iter().flatten() {
+            hasher.write_str(&layer.identifier);
+            hasher.write_i32(layer.c_wid);
+            hasher.write_i32(layer.c_hei);
+            hasher.write_i32(layer.grid_size);
+            for value in &layer.int_grid_csv {
+                hasher.write_i32(*value);
+            }
+            for entity in &layer.entity_instances {
+                hasher.write_str(&entity.identifier);
+                hasher.write_str(&entity.iid);
+                hasher.write_i32(entity.px.x);
+                hasher.write_i32(entity.px.y);
+                hasher.write_i32(entity.width);
+                hasher.write_i32(entity.height);
+                hasher.write(&entity.pivot.x.to_le_bytes());
+                hasher.write(&entity.pivot.y.to_le_bytes());
+                // Paths, player handles, door links and whatever fields come
+                // next, in the order they are in the file
+                let fields = serde_json::to_vec(&entity.field_instances)
+                    .expect("LDtk fields should serialize");
+                hasher.write(&fields);
+            }
+        }
+    }
+}
+
+/// Covers the crate version, the input encoding,
+/// the rollback registry and the LDtk project
+pub fn compute_protocol_version(
+    mut commands: Commands,
+    registry: Res<RollbackRegistry>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let mut hasher = StableHasher::new();
+
+    hasher.write_str(env!("CARGO_PKG_VERSION"));
+
+    hasher.write_str(std::any::type_name::<MultiplayerConfig>());
+    hasher.write(&[
+        INPUT_UP,
+        INPUT_DOWN,
+        INPUT_LEFT,
+        INPUT_RIGHT,
+        INPUT_FIRE,
+        INPUT_USE,
+        INPUT_LEVELS_READY,
+    ]);
+
+    for type_name in &registry.0 {
+        hasher.write_str(type_name);
+    }
+
+    let ldtk_project = ldtk_project_assets
+        .get(ldtk_projects.single())
+        .expect("Project should be loaded before playing");
+    hash_ldtk_project(&mut hasher, ldtk_project);
+
+    info!("Protocol version {:016x}", hasher.0);
+    commands.insert_resource(ProtocolVersion(hasher.0));
+}
+
+/// Announces our version to every new peer and collects theirs
+pub fn exchange_versions(
+    mut commands: Commands,
+    mut socket: ResMut<MatchboxSocket>,
+    mut peer_versions: ResMut<PeerVersions>,
+    mut peer_states: EventReader<PeerStateEvent>,
+    mut versions: EventReader<PeerVersionEvent>,
+    version: Res<ProtocolVersion>,
+    match_mode: Res<MatchMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for event in peer_states.read() {
+        match event.state {
+            PeerState::Connected => send_version(&mut socket, event.peer, version.0),
+            PeerState::Disconnected => {
+                peer_versions.0.remove(&event.peer);
+            }
+        }
+    }
+
+    for event in versions.read() {
+        let theirs = event.version;
+        peer_versions.0.insert(event.peer, theirs);
+
+        if theirs != version.0 {
+            error!(
+                "Peer {} runs version {:016x}, ours is {:016x}",
+                event.peer, theirs, version.0
+            );
+            // Refuse the match instead of desyncing
+            fail_connection(
+                &mut commands,
+                &mut next_state,
+                &match_mode,
+                ConnectionError::VersionMismatch {
+                    peer: event.peer,
+                    ours: version.0,
+                    theirs,
+                },
+            );
+        }
+    }
+}
+
+/// Whether every connected peer has announced a matching version
+pub fn all_peers_verified(
+    socket: &MatchboxSocket,
+    peer_versions: &PeerVersions,
+    version: &ProtocolVersion,
+) -> bool {
+    socket
+        .connected_peers()
+        .all(|peer| peer_versions.0.get(&peer) == Some(&version.0))
+}
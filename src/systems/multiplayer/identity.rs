@@ -0,0 +1,153 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_egui::egui;
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::messages::{PeerMessage, PeerMessageEvent, PeerMessageExt, PeerStateEvent, peer_label};
+
+/// Longest name a peer can pick, longer ones get cut
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Colors players can pick from
+pub const PLAYER_COLORS: [[u8; 3]; 6] = [
+    [230, 80, 70],
+    [240, 170, 50],
+    [110, 200, 90],
+    [70, 170, 230],
+    [150, 110, 230],
+    [230, 110, 190],
+];
+
+/// How a player shows up to the others, picked before connecting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlayerIdentity {
+    pub name: String,
+    pub color: [u8; 3],
+}
+
+impl PlayerIdentity {
+    pub fn egui_color(&self) -> egui::Color32 {
+        let [r, g, b] = self.color;
+        egui::Color32::from_rgb(r, g, b)
+    }
+
+    /// Identity of a bot, numbered from 1
+    pub fn bot(number: usize) -> Self {
+        PlayerIdentity {
+            name: format!("Bot {}", number),
+            color: [150, 170, 200],
+        }
+    }
+
+    /// Trims what came over the network into something we are willing to draw
+    fn sanitized(mut self) -> Self {
+        self.name = self
+            .name
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_NAME_LENGTH)
+            .collect::<String>()
+            .trim()
+            .to_string();
+        self
+    }
+}
+
+/// Our own name and color
+#[derive(Resource, Clone, PartialEq)]
+pub struct LocalIdentity(pub PlayerIdentity);
+
+impl Default for LocalIdentity {
+    fn default() -> Self {
+        LocalIdentity(PlayerIdentity {
+            name: format!("Player {}", fastrand::u16(1000..10000)),
+            color: PLAYER_COLORS[fastrand::usize(..PLAYER_COLORS.len())],
+        })
+    }
+}
+
+/// Names and colors the connected peers picked
+#[derive(Resource, Default)]
+pub struct PeerIdentities(pub HashMap<PeerId, PlayerIdentity>);
+
+/// Color of peers that didn't tell us theirs
+const UNKNOWN_COLOR: [u8; 3] = [200, 200, 200];
+
+impl PeerIdentities {
+    /// Name and color of a peer, with its shortened id as the name until it told us one
+    pub fn identity(&self, peer: PeerId) -> PlayerIdentity {
+        let mut identity = self.0.get(&peer).cloned().unwrap_or(PlayerIdentity {
+            name: String::new(),
+            color: UNKNOWN_COLOR,
+        });
+        if identity.name.is_empty() {
+            identity.name = peer_label(peer);
+        }
+        identity
+    }
+
+    /// Whether every connected peer told us who they are
+    pub fn all_known(&self, socket: &MatchboxSocket) -> bool {
+        socket
+            .connected_peers()
+            .all(|peer| self.0.contains_key(&peer))
+    }
+}
+
+/// Who controls a player entity, only for display, it is not part of the simulation
+#[derive(Component, Clone, Debug)]
+pub struct PlayerTag {
+    pub identity: PlayerIdentity,
+    /// The peer simulating the player's inputs, `None` for ours
+    pub peer: Option<PeerId>,
+}
+
+/// Tells newly connected peers who we are, and everybody when we change our mind
+pub fn exchange_identities(
+    mut socket: ResMut<MatchboxSocket>,
+    local: Res<LocalIdentity>,
+    mut identities: ResMut<PeerIdentities>,
+    mut peer_states: EventReader<PeerStateEvent>,
+    mut messages: EventReader<PeerMessageEvent>,
+) {
+    if local.is_changed() && !local.is_added() {
+        socket.broadcast_message(&PeerMessage::Identity(local.0.clone()));
+    }
+
+    // Peers that left are kept, the chat still shows what they said
+    for event in peer_states.read() {
+        if matches!(event.state, PeerState::Connected) {
+            socket.send_message(event.peer, &PeerMessage::Identity(local.0.clone()));
+        }
+    }
+
+    for event in messages.read() {
+        if let PeerMessage::Identity(identity) = &event.message {
+            identities
+                .0
+                .insert(event.peer, identity.clone().sanitized());
+        }
+    }
+}
+
+/// Name field and color swatches for our identity, only touching the resource on changes
+pub fn identity_editor(ui: &mut egui::Ui, local: &mut ResMut<LocalIdentity>) {
+    let mut identity = local.0.clone();
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        ui.add(egui::TextEdit::singleline(&mut identity.name).char_limit(MAX_NAME_LENGTH));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Color");
+        for color in PLAYER_COLORS {
+            let [r, g, b] = color;
+            let swatch = egui::Button::new("  ")
+                .fill(egui::Color32::from_rgb(r, g, b))
+                .selected(identity.color == color);
+            if ui.add(swatch).clicked() {
+                identity.color = color;
+            }
+        }
+    });
+    local.set_if_neq(LocalIdentity(identity));
+}
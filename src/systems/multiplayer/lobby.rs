@@ -0,0 +1,212 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+
+use crate::{config::NUM_PLAYERS, game::GameState, systems::levels::LevelProgress};
+
+use super::{
+    identity::{LocalIdentity, PeerIdentities, identity_editor},
+    messages::{PeerMessage, PeerMessageEvent, PeerMessageExt, PeerPings, PeerStateEvent},
+    room::MatchMode,
+};
+
+/// Who in the lobby is ready to start
+#[derive(Resource, Default)]
+pub struct LobbyReadiness {
+    pub local: bool,
+    peers: HashMap<PeerId, bool>,
+}
+
+impl LobbyReadiness {
+    pub fn is_ready(&self, peer: PeerId) -> bool {
+        self.peers.get(&peer).copied().unwrap_or(false)
+    }
+
+    pub fn all_ready(&self, socket: &MatchboxSocket) -> bool {
+        self.local && socket.connected_peers().all(|peer| self.is_ready(peer))
+    }
+
+    /// Makes everybody agree again after the settings changed, the peers that are
+    /// still ready tell us so again once they saw the change
+    fn reset(&mut self, socket: &mut MatchboxSocket) {
+        self.peers.clear();
+        if self.local {
+            self.local = false;
+            socket.broadcast_message(&PeerMessage::Ready(false));
+        }
+    }
+}
+
+/// Match options decided by the lobby host
+#[derive(Resource, Default, Clone, Copy)]
+pub struct MatchSettings {
+    /// Player slots filled by bots instead of humans
+    pub bots: usize,
+}
+
+impl MatchSettings {
+    pub fn humans(&self) -> usize {
+        NUM_PLAYERS - self.bots
+    }
+}
+
+/// The peer with the lowest id hosts the lobby and owns the bots,
+/// every peer comes to the same conclusion without talking about it
+pub fn lobby_host(socket: &mut MatchboxSocket) -> Option<PeerId> {
+    let our_id = socket.id()?;
+    socket
+        .connected_peers()
+        .chain(std::iter::once(our_id))
+        .min()
+}
+
+pub fn track_lobby(
+    mut socket: ResMut<MatchboxSocket>,
+    mut readiness: ResMut<LobbyReadiness>,
+    mut settings: ResMut<MatchSettings>,
+    mut peer_states: EventReader<PeerStateEvent>,
+    mut messages: EventReader<PeerMessageEvent>,
+) {
+    let host = lobby_host(&mut socket);
+    let is_host = host.is_some() && host == socket.id();
+
+    for event in peer_states.read() {
+        match event.state {
+            // Late joiners need to know we are already waiting for them
+            PeerState::Connected => {
+                socket.send_message(event.peer, &PeerMessage::Ready(readiness.local));
+                if is_host {
+                    socket.send_message(event.peer, &PeerMessage::Bots(settings.bots));
+                }
+            }
+            PeerState::Disconnected => {
+                readiness.peers.remove(&event.peer);
+            }
+        }
+    }
+
+    for event in messages.read() {
+        match event.message {
+            PeerMessage::Ready(ready) => {
+                readiness.peers.insert(event.peer, ready);
+            }
+            PeerMessage::Bots(bots) if Some(event.peer) == host => {
+                let bots = bots.min(NUM_PLAYERS - 1);
+                if settings.bots != bots {
+                    settings.bots = bots;
+                    readiness.reset(&mut socket);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether the level the match starts in is spawned here, with everything in it
+fn start_level_spawned(
+    progress: &LevelProgress,
+    levels: &Query<(&LevelIid, Option<&Children>)>,
+) -> bool {
+    levels.iter().any(|(iid, children)| {
+        iid.as_str() == progress.level_iid()
+            && children.is_some_and(|children| !children.is_empty())
+    })
+}
+
+/// Shows the room, the peers in it and lets us toggle readiness.
+///
+/// We can only get ready once the start level is spawned, so no match starts
+/// before every peer has its level.
+#[allow(clippy::too_many_arguments)]
+pub fn lobby_window(
+    mut contexts: EguiContexts,
+    mut socket: ResMut<MatchboxSocket>,
+    mut readiness: ResMut<LobbyReadiness>,
+    mut settings: ResMut<MatchSettings>,
+    pings: Res<PeerPings>,
+    match_mode: Res<MatchMode>,
+    mut local_identity: ResMut<LocalIdentity>,
+    identities: Res<PeerIdentities>,
+    progress: Res<LevelProgress>,
+    levels: Query<(&LevelIid, Option<&Children>)>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let level_spawned = start_level_spawned(&progress, &levels);
+    if !level_spawned && readiness.local {
+        readiness.local = false;
+        socket.broadcast_message(&PeerMessage::Ready(false));
+    }
+    let host = lobby_host(&mut socket);
+    let is_host = host.is_some() && host == socket.id();
+
+    egui::Window::new("Lobby")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0., 16.])
+        .show(contexts.ctx_mut(), |ui| {
+            match &*match_mode {
+                MatchMode::QuickMatch => {
+                    ui.label("Quick match");
+                }
+                MatchMode::Private(code) => {
+                    ui.label("Share this code with the other players:");
+                    ui.heading(code.to_string());
+                }
+                MatchMode::SyncTest => {
+                    ui.label("Sync test");
+                }
+            }
+            ui.separator();
+
+            identity_editor(ui, &mut local_identity);
+            ui.separator();
+
+            let peers: Vec<PeerId> = socket.connected_peers().collect();
+            let missing = settings.humans().saturating_sub(peers.len() + 1);
+            if missing > 0 {
+                ui.label(format!("Waiting for {} more players...", missing));
+            }
+            for peer in peers {
+                let status = if readiness.is_ready(peer) {
+                    "ready"
+                } else {
+                    "not ready"
+                };
+                let ping = pings
+                    .0
+                    .get(&peer)
+                    .map(|rtt| format!("{:.0} ms", rtt))
+                    .unwrap_or_default();
+                let identity = identities.identity(peer);
+                ui.horizontal(|ui| {
+                    ui.colored_label(identity.egui_color(), identity.name);
+                    ui.label(format!("- {} {}", status, ping));
+                });
+            }
+            ui.separator();
+
+            // Quick match rooms always pair up full groups of humans
+            let private = matches!(*match_mode, MatchMode::Private(_));
+            ui.add_enabled_ui(is_host && private, |ui| {
+                let bots = egui::Slider::new(&mut settings.bots, 0..=NUM_PLAYERS - 1).text("Bots");
+                if ui.add(bots).changed() {
+                    socket.broadcast_message(&PeerMessage::Bots(settings.bots));
+                    readiness.reset(&mut socket);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let ready = ui.add_enabled(
+                    level_spawned,
+                    egui::Checkbox::new(&mut readiness.local, "Ready"),
+                );
+                if ready.changed() {
+                    socket.broadcast_message(&PeerMessage::Ready(readiness.local));
+                }
+                if ui.button("Leave").clicked() {
+                    next_state.set(GameState::MainMenu);
+                }
+            });
+        });
+}
@@ -0,0 +1,212 @@
+use bevy::{prelude::*, utils::HashMap};
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use super::{RELIABLE_CHANNEL, identity::PlayerIdentity};
+
+/// How often the peers get pinged
+const PING_INTERVAL_SECS: f32 = 1.0;
+
+/// First bytes of a version packet, followed by the version as a little-endian u64.
+///
+/// Versions are sent outside of `PeerMessage` so that builds disagreeing on the
+/// messages can still tell they do. Bincode starts every message with its variant
+/// index as a little-endian u32, which never gets this high.
+const VERSION_PACKET_TAG: [u8; 4] = [0xff; 4];
+
+/// Everything peers tell each other outside of GGRS.
+///
+/// These messages are not synchronized with the rollback frames,
+/// so nothing in the simulation may depend on them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum PeerMessage {
+    /// Name and color of the sender, sent to every newly connected peer and after changing them
+    Identity(PlayerIdentity),
+    /// Text chat
+    Chat(String),
+    Emote(Emote),
+    /// Lobby readiness
+    Ready(bool),
+    /// Number of bot players, only sent by the lobby host
+    Bots(usize),
+    /// Carries the sender's clock, answered with a `Pong` carrying the same value
+    Ping(f64),
+    Pong(f64),
+    /// The sender left the match
+    MatchOver,
+    /// The sender wants a rematch in the room with this code
+    Rematch(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emote {
+    Wave,
+    Laugh,
+    Cheer,
+    Cry,
+}
+
+impl Emote {
+    pub const ALL: [Emote; 4] = [Emote::Wave, Emote::Laugh, Emote::Cheer, Emote::Cry];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Emote::Wave => "Wave",
+            Emote::Laugh => "Laugh",
+            Emote::Cheer => "Cheer",
+            Emote::Cry => "Cry",
+        }
+    }
+
+    /// Used in the chat log, as in "<name> waves"
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Emote::Wave => "waves",
+            Emote::Laugh => "laughs",
+            Emote::Cheer => "cheers",
+            Emote::Cry => "cries",
+        }
+    }
+}
+
+/// A message that arrived from a peer
+#[derive(Event, Debug)]
+pub struct PeerMessageEvent {
+    pub peer: PeerId,
+    pub message: PeerMessage,
+}
+
+/// A peer announced its protocol version
+#[derive(Event, Debug)]
+pub struct PeerVersionEvent {
+    pub peer: PeerId,
+    pub version: u64,
+}
+
+/// A peer connected to or disconnected from the socket
+#[derive(Event, Debug)]
+pub struct PeerStateEvent {
+    pub peer: PeerId,
+    pub state: PeerState,
+}
+
+/// The socket stopped working, the matchmaking server is gone or refused us
+#[derive(Event, Debug)]
+pub struct SocketClosed;
+
+/// Short name for a peer, until it tells us a better one
+pub fn peer_label(peer: PeerId) -> String {
+    peer.to_string().chars().take(8).collect()
+}
+
+/// Round trip times to the peers in milliseconds
+#[derive(Resource, Default)]
+pub struct PeerPings(pub HashMap<PeerId, f32>);
+
+pub trait PeerMessageExt {
+    fn send_message(&mut self, peer: PeerId, message: &PeerMessage);
+    fn broadcast_message(&mut self, message: &PeerMessage);
+}
+
+impl PeerMessageExt for MatchboxSocket {
+    fn send_message(&mut self, peer: PeerId, message: &PeerMessage) {
+        let packet = bincode::serialize(message).expect("Peer messages should serialize");
+        self.channel_mut(RELIABLE_CHANNEL)
+            .send(packet.into_boxed_slice(), peer);
+    }
+
+    fn broadcast_message(&mut self, message: &PeerMessage) {
+        let peers: Vec<PeerId> = self.connected_peers().collect();
+        for peer in peers {
+            self.send_message(peer, message);
+        }
+    }
+}
+
+/// Sends our protocol version in the fixed layout every build understands
+pub fn send_version(socket: &mut MatchboxSocket, peer: PeerId, version: u64) {
+    let mut packet = VERSION_PACKET_TAG.to_vec();
+    packet.extend(version.to_le_bytes());
+    socket
+        .channel_mut(RELIABLE_CHANNEL)
+        .send(packet.into_boxed_slice(), peer);
+}
+
+fn parse_version(packet: &[u8]) -> Option<u64> {
+    let bytes = packet.strip_prefix(&VERSION_PACKET_TAG)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Turns peer changes and packets on the reliable channel into events
+pub fn receive_peer_messages(
+    mut socket: ResMut<MatchboxSocket>,
+    mut state_events: EventWriter<PeerStateEvent>,
+    mut message_events: EventWriter<PeerMessageEvent>,
+    mut version_events: EventWriter<PeerVersionEvent>,
+    mut closed_events: EventWriter<SocketClosed>,
+) {
+    let peers = match socket.try_update_peers() {
+        Ok(peers) => peers,
+        Err(err) => {
+            warn!("Socket closed: {:?}", err);
+            closed_events.send(SocketClosed);
+            return;
+        }
+    };
+    for (peer, state) in peers {
+        info!("Peer {} is now {:?}", peer, state);
+        state_events.send(PeerStateEvent { peer, state });
+    }
+
+    for (peer, packet) in socket.channel_mut(RELIABLE_CHANNEL).receive() {
+        if let Some(version) = parse_version(&packet) {
+            version_events.send(PeerVersionEvent { peer, version });
+            continue;
+        }
+        match bincode::deserialize::<PeerMessage>(&packet) {
+            Ok(message) => {
+                message_events.send(PeerMessageEvent { peer, message });
+            }
+            Err(err) => warn!("Malformed message from {}: {}", peer, err),
+        }
+    }
+}
+
+pub fn send_pings(
+    mut socket: ResMut<MatchboxSocket>,
+    time: Res<Time<Real>>,
+    mut timer: Local<Option<Timer>>,
+) {
+    let timer =
+        timer.get_or_insert_with(|| Timer::from_seconds(PING_INTERVAL_SECS, TimerMode::Repeating));
+    if timer.tick(time.delta()).just_finished() {
+        socket.broadcast_message(&PeerMessage::Ping(time.elapsed_secs_f64()));
+    }
+}
+
+pub fn answer_pings(
+    mut socket: ResMut<MatchboxSocket>,
+    mut messages: EventReader<PeerMessageEvent>,
+    mut peer_states: EventReader<PeerStateEvent>,
+    mut pings: ResMut<PeerPings>,
+    time: Res<Time<Real>>,
+) {
+    for event in peer_states.read() {
+        if matches!(event.state, PeerState::Disconnected) {
+            pings.0.remove(&event.peer);
+        }
+    }
+
+    for event in messages.read() {
+        match event.message {
+            PeerMessage::Ping(sent_at) => {
+                socket.send_message(event.peer, &PeerMessage::Pong(sent_at));
+            }
+            PeerMessage::Pong(sent_at) => {
+                let rtt = (time.elapsed_secs_f64() - sent_at) * 1000.;
+                pings.0.insert(event.peer, rtt as f32);
+            }
+            _ => {}
+        }
+    }
+}
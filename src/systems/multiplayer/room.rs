@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+
+use crate::{config::NUM_PLAYERS, game::GameState};
+
+use super::identity::{LocalIdentity, identity_editor};
+
+const MATCHBOX_SERVER: &str = "ws://127.0.0.1:3536";
+/// Scope shared by everyone looking for a quick match
+const QUICK_MATCH_SCOPE: &str = "wasm_test";
+
+const ROOM_CODE_LENGTH: usize = 5;
+/// No 0/O or 1/I, so codes survive being read out loud
+const ROOM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Short human-readable name of a private matchbox room
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct RoomCode(String);
+
+impl RoomCode {
+    pub fn generate() -> Self {
+        let code = (0..ROOM_CODE_LENGTH)
+            .map(|_| ROOM_CODE_ALPHABET[fastrand::usize(..ROOM_CODE_ALPHABET.len())] as char)
+            .collect();
+        Self(code)
+    }
+
+    /// Normalizes a code typed in by the player, `None` if it can't be a valid code
+    pub fn parse(input: &str) -> Option<Self> {
+        let code = input.trim().to_ascii_uppercase();
+        if code.len() != ROOM_CODE_LENGTH || !code.bytes().all(|c| ROOM_CODE_ALPHABET.contains(&c))
+        {
+            return None;
+        }
+        Some(Self(code))
+    }
+}
+
+impl std::fmt::Display for RoomCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// How the player wants to find the other peers.
+/// Entering `GameState::Connecting` opens the matchbox socket for it.
+#[derive(Resource, Clone, Debug)]
+pub enum MatchMode {
+    /// Pair up with whoever connects next
+    QuickMatch,
+    /// Play only with peers that entered the same room code
+    Private(RoomCode),
+    /// Offline GGRS SyncTest session against bots, for checking determinism
+    SyncTest,
+}
+
+impl MatchMode {
+    /// Matchbox room to connect to, `None` when playing offline
+    pub fn room_url(&self) -> Option<String> {
+        match self {
+            // next=N -> make room connect groups of N as they connect
+            MatchMode::QuickMatch => Some(format!(
+                "{MATCHBOX_SERVER}/{QUICK_MATCH_SCOPE}?next={NUM_PLAYERS}"
+            )),
+            MatchMode::Private(code) => Some(format!("{MATCHBOX_SERVER}/{code}")),
+            MatchMode::SyncTest => None,
+        }
+    }
+}
+
+/// Lets the player pick between quick match, creating a room and joining one
+pub fn match_menu(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    mut code_input: Local<String>,
+    mut local_identity: ResMut<LocalIdentity>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let mut chosen = None;
+    egui::Window::new("Play")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            identity_editor(ui, &mut local_identity);
+            ui.separator();
+            if ui.button("Quick match").clicked() {
+                chosen = Some(MatchMode::QuickMatch);
+            }
+            ui.separator();
+            if ui.button("Create match").clicked() {
+                chosen = Some(MatchMode::Private(RoomCode::generate()));
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Room code");
+                ui.text_edit_singleline(&mut *code_input);
+            });
+            let code = RoomCode::parse(&code_input);
+            let join = ui.add_enabled(code.is_some(), egui::Button::new("Join match"));
+            if let Some(code) = code.filter(|_| join.clicked()) {
+                chosen = Some(MatchMode::Private(code));
+            }
+            #[cfg(feature = "dev")]
+            {
+                ui.separator();
+                if ui.button("Sync test").clicked() {
+                    chosen = Some(MatchMode::SyncTest);
+                }
+            }
+        });
+
+    let Some(mode) = chosen else {
+        return;
+    };
+    // Sync tests are played offline right away
+    next_state.set(if mode.room_url().is_some() {
+        GameState::Connecting
+    } else {
+        GameState::InGame
+    });
+    commands.insert_resource(mode);
+}
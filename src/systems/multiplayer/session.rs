@@ -0,0 +1,294 @@
+use avian2d::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use bevy_ggrs::{
+    ConfirmedFrameCount, LocalInputs, LocalPlayers, RollbackFrameCount, ggrs::GgrsEvent, prelude::*,
+};
+use bevy_matchbox::{MatchboxSocket, prelude::*};
+
+use crate::{
+    config::MultiplayerConfig,
+    game::{GameState, PauseState},
+    systems::{
+        frame_logging::{CurrentSessionFrame, RollbackStatus},
+        interactibles::InteractibleStates,
+        levels::{LevelProgress, LevelRevision},
+        player::PlayerSpawnState,
+        streaming::StreamedLevels,
+    },
+};
+
+use super::{
+    messages::{PeerMessage, PeerMessageEvent, PeerMessageExt, PeerStateEvent},
+    room::{MatchMode, RoomCode},
+};
+
+/// Why the last match ended
+#[derive(Resource, Debug, Clone, Copy)]
+pub enum MatchEnd {
+    /// We left the match
+    Left,
+    /// A peer left the match
+    PeerLeft,
+    /// A peer stopped responding
+    Disconnected,
+    /// The LDtk project changed on disk while playing
+    LevelsChanged,
+}
+
+/// Rematch requests, the match restarts once every peer asked for one
+#[derive(Resource, Default)]
+pub struct RematchRequests {
+    proposed: Option<RoomCode>,
+    peers: HashMap<PeerId, RoomCode>,
+}
+
+/// Ends the match when a peer drops out of the GGRS session
+pub fn handle_session_events(
+    mut commands: Commands,
+    mut session: ResMut<Session<MultiplayerConfig>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Session::P2P(session) = &mut *session else {
+        return;
+    };
+    for event in session.events() {
+        match event {
+            GgrsEvent::Disconnected { addr } => {
+                warn!("Peer {} disconnected, ending match", addr);
+                commands.insert_resource(MatchEnd::Disconnected);
+                next_state.set(GameState::Results);
+            }
+            GgrsEvent::DesyncDetected {
+                frame,
+                local_checksum,
+                remote_checksum,
+                addr,
+            } => {
+                error!(
+                    "Desync with {} on frame {}: {:x} != {:x}",
+                    addr, frame, local_checksum, remote_checksum
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ends a networked match when the LDtk project changes on disk. The peers can't
+/// agree on a frame to switch to the new levels on, playing on would desync.
+pub fn end_match_on_level_reload(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<LdtkProject>>,
+    session: Res<Session<MultiplayerConfig>>,
+    mut socket: ResMut<MatchboxSocket>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let reloaded = events
+        .read()
+        .any(|event| matches!(event, AssetEvent::Modified { .. }));
+    if !reloaded || !matches!(*session, Session::P2P(_)) {
+        return;
+    }
+    warn!("Levels changed on disk, leaving the match");
+    socket.broadcast_message(&PeerMessage::MatchOver);
+    commands.insert_resource(MatchEnd::LevelsChanged);
+    next_state.set(GameState::Results);
+}
+
+/// Opens and closes the pause menu on Escape
+pub fn toggle_pause(
+    keys: Res<ButtonInput<KeyCode>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    if !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    next_pause_state.set(match pause_state.get() {
+        PauseState::Running => PauseState::Paused,
+        PauseState::Paused => PauseState::Running,
+    });
+}
+
+/// Overlay of a paused match, leaving it from here ends it for everyone
+pub fn pause_menu(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    socket: Option<ResMut<MatchboxSocket>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    egui::Window::new("Paused")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("The match goes on for the others, your character stands still.");
+            ui.separator();
+            if ui.button("Resume").clicked() {
+                next_pause_state.set(PauseState::Running);
+            }
+            if ui.button("Leave match").clicked() {
+                info!("Leaving match");
+                // Sync tests have nobody to tell
+                if let Some(mut socket) = socket {
+                    socket.broadcast_message(&PeerMessage::MatchOver);
+                }
+                commands.insert_resource(MatchEnd::Left);
+                next_state.set(GameState::Results);
+            }
+        });
+}
+
+/// Handles match control messages from the peers
+pub fn receive_match_messages(
+    mut commands: Commands,
+    mut rematch: ResMut<RematchRequests>,
+    mut peer_states: EventReader<PeerStateEvent>,
+    mut messages: EventReader<PeerMessageEvent>,
+    game_state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for event in peer_states.read() {
+        if matches!(event.state, PeerState::Disconnected) {
+            rematch.peers.remove(&event.peer);
+        }
+    }
+
+    for event in messages.read() {
+        match &event.message {
+            PeerMessage::MatchOver => {
+                if *game_state.get() == GameState::InGame {
+                    info!("Peer {} left the match", event.peer);
+                    commands.insert_resource(MatchEnd::PeerLeft);
+                    next_state.set(GameState::Results);
+                }
+            }
+            PeerMessage::Rematch(code) => {
+                let Some(code) = RoomCode::parse(code) else {
+                    warn!("Peer {} proposed an invalid room code", event.peer);
+                    continue;
+                };
+                info!("Peer {} wants a rematch", event.peer);
+                rematch.peers.insert(event.peer, code);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Removes the GGRS session and everything it simulated, whichever way the match ended
+pub fn teardown_session(
+    mut commands: Commands,
+    rollback_q: Query<Entity, With<Rollback>>,
+    mut collisions: ResMut<Collisions>,
+    mut spawn_state: ResMut<PlayerSpawnState>,
+    level_revision: Res<LevelRevision>,
+) {
+    info!("Tearing down session");
+    commands.remove_resource::<Session<MultiplayerConfig>>();
+    commands.remove_resource::<LocalPlayers>();
+    commands.remove_resource::<LocalInputs<MultiplayerConfig>>();
+
+    for entity in &rollback_q {
+        commands.entity(entity).despawn_recursive();
+    }
+    collisions.retain(|_| false);
+
+    // The next session starts counting from frame 0 again
+    commands.insert_resource(RollbackFrameCount::default());
+    commands.insert_resource(ConfirmedFrameCount::default());
+    commands.insert_resource(RollbackStatus::default());
+    commands.insert_resource(CurrentSessionFrame::default());
+    commands.insert_resource(RematchRequests::default());
+    // Reloads from before this match are already loaded
+    commands.insert_resource(LevelProgress {
+        revision: level_revision.0,
+        ..default()
+    });
+    commands.insert_resource(InteractibleStates::default());
+    commands.insert_resource(StreamedLevels::default());
+    spawn_state.reset();
+}
+
+/// Reconnects to a room once every peer asked for a rematch
+pub fn start_rematch(
+    mut commands: Commands,
+    socket: Res<MatchboxSocket>,
+    rematch: Res<RematchRequests>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(proposed) = &rematch.proposed else {
+        return;
+    };
+    let mut peers = socket.connected_peers().peekable();
+    if peers.peek().is_none() || !peers.all(|peer| rematch.peers.contains_key(&peer)) {
+        return;
+    }
+
+    // Private rooms propose their own code, quick matches agree on the smallest proposal
+    let code = rematch
+        .peers
+        .values()
+        .chain(std::iter::once(proposed))
+        .min()
+        .unwrap()
+        .clone();
+    info!("Starting rematch in room {}", code);
+
+    commands.remove_resource::<MatchboxSocket>();
+    commands.insert_resource(MatchMode::Private(code));
+    next_state.set(GameState::Connecting);
+}
+
+/// Sync tests and matches that lost their socket can only go back to the menu
+pub fn results_screen(
+    mut contexts: EguiContexts,
+    mut socket: Option<ResMut<MatchboxSocket>>,
+    mut rematch: ResMut<RematchRequests>,
+    match_end: Res<MatchEnd>,
+    match_mode: Res<MatchMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let peers_left = socket
+        .as_ref()
+        .is_none_or(|socket| socket.connected_peers().next().is_none());
+
+    egui::Window::new("Match over")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(match *match_end {
+                MatchEnd::Left => "You left the match.",
+                MatchEnd::PeerLeft => "Your opponent left the match.",
+                MatchEnd::Disconnected => "Your opponent disconnected.",
+                MatchEnd::LevelsChanged => "The levels changed on disk.",
+            });
+            ui.separator();
+
+            if peers_left {
+                ui.label("Nobody left to rematch.");
+            } else if rematch.proposed.is_some() {
+                ui.label("Waiting for the other players...");
+            } else if let Some(socket) = &mut socket {
+                if !rematch.peers.is_empty() {
+                    ui.label("Your opponent wants a rematch!");
+                }
+                if ui.button("Rematch").clicked() {
+                    let code = match &*match_mode {
+                        MatchMode::Private(code) => code.clone(),
+                        MatchMode::QuickMatch | MatchMode::SyncTest => RoomCode::generate(),
+                    };
+                    socket.broadcast_message(&PeerMessage::Rematch(code.to_string()));
+                    rematch.proposed = Some(code);
+                }
+            }
+
+            if ui.button("Back to menu").clicked() {
+                next_state.set(GameState::MainMenu);
+            }
+        });
+}
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+use bevy_ggrs::{LocalPlayers, ggrs, prelude::*};
+
+use crate::{config::*, systems::GameAssets};
+
+use super::{
+    INPUT_DELAY,
+    identity::{LocalIdentity, PeerIdentities},
+    room::MatchMode,
+    spawn_match_players,
+};
+
+/// Whether the running session is a SyncTest session
+pub fn in_sync_test(session: Option<Res<Session<MultiplayerConfig>>>) -> bool {
+    matches!(session.as_deref(), Some(Session::SyncTest(_)))
+}
+
+/// How many frames a SyncTest session rolls back and resimulates every frame
+const CHECK_DISTANCE: usize = 2;
+
+/// Starts an offline SyncTest session, the first player is played from the keyboard
+/// and the others by bots.
+///
+/// Every frame gets resimulated and its checksums compared, so anything that isn't
+/// deterministic shows up right away without needing a second peer.
+pub fn start_synctest_session(
+    mut commands: Commands,
+    match_mode: Res<MatchMode>,
+    game_assets: Res<GameAssets>,
+    local_identity: Res<LocalIdentity>,
+) {
+    if !matches!(*match_mode, MatchMode::SyncTest) {
+        return;
+    }
+    info!("Starting sync test session");
+
+    let players = vec![PlayerType::Local; NUM_PLAYERS];
+    let mut session_builder = ggrs::SessionBuilder::<MultiplayerConfig>::new()
+        .with_num_players(NUM_PLAYERS)
+        .with_check_distance(CHECK_DISTANCE)
+        .with_input_delay(INPUT_DELAY);
+    for (i, player) in players.iter().enumerate() {
+        session_builder = session_builder
+            .add_player(*player, i)
+            .expect("failed to add player");
+    }
+
+    let local_player_handles = spawn_match_players(
+        &mut commands,
+        &game_assets,
+        &players,
+        1,
+        &local_identity.0,
+        &PeerIdentities::default(),
+    );
+    commands.insert_resource(LocalPlayers(local_player_handles));
+
+    let session = session_builder
+        .start_synctest_session()
+        .expect("failed to start sync test session");
+    commands.insert_resource(Session::SyncTest(session));
+}
@@ -0,0 +1,190 @@
+use bevy::{prelude::*, transform::TransformSystem, window::PrimaryWindow};
+
+use super::pixel_perfect::{
+    CameraSlot, GameCamera, displayed_canvas_height, parallax_render_layer,
+};
+
+/// The background image in the sizes it ships in, smallest first,
+/// with their height in pixels
+pub const BACKGROUND_VARIANTS: [(&str, f32); 3] = [
+    ("atlas/background.png", 171.),
+    ("atlas/background_medium.png", 342.),
+    ("atlas/background_large.png", 512.),
+];
+/// Width over height of the background image, the same for every variant
+const BACKGROUND_ASPECT: f32 = 1.5;
+
+/// How one layer of the background is drawn
+pub struct ParallaxLayerConfig {
+    /// Z of the layer, behind the levels
+    pub depth: f32,
+    /// How fast the layer scrolls compared to the level, 0 stays put behind the camera
+    /// and 1 moves along with the level
+    pub scroll: Vec2,
+    /// Height of the layer compared to the view
+    pub scale: f32,
+    pub color: Color,
+}
+
+pub const PARALLAX_LAYERS: [ParallaxLayerConfig; 2] = [
+    // Distant mountains
+    ParallaxLayerConfig {
+        depth: -100.,
+        scroll: Vec2::new(0.05, 0.02),
+        scale: 1.1,
+        color: Color::WHITE,
+    },
+    // Closer, hazy copy for depth
+    ParallaxLayerConfig {
+        depth: -90.,
+        scroll: Vec2::new(0.25, 0.1),
+        scale: 1.6,
+        color: Color::srgba(0.8, 0.85, 1.0, 0.25),
+    },
+];
+
+/// A background sprite, one of `PARALLAX_LAYERS` seen by one game camera
+#[derive(Component)]
+pub struct ParallaxLayer {
+    pub config: usize,
+    pub camera: Entity,
+}
+
+/// Which of `BACKGROUND_VARIANTS` is loaded
+#[derive(Resource, Default, PartialEq)]
+pub struct BackgroundVariant(pub usize);
+
+/// The smallest variant that isn't stretched on a screen of this height
+fn variant_for_height(physical_height: f32) -> usize {
+    BACKGROUND_VARIANTS
+        .iter()
+        .position(|(_, height)| *height >= physical_height)
+        .unwrap_or(BACKGROUND_VARIANTS.len() - 1)
+}
+
+/// Gives every new game camera a background of its own, split screen
+/// viewports scroll it differently
+pub fn spawn_parallax_layers(
+    mut commands: Commands,
+    cameras: Query<(Entity, &CameraSlot), Added<GameCamera>>,
+    asset_server: Res<AssetServer>,
+    variant: Res<BackgroundVariant>,
+) {
+    let image = asset_server.load(BACKGROUND_VARIANTS[variant.0].0);
+    for (camera, slot) in &cameras {
+        for (i, layer) in PARALLAX_LAYERS.iter().enumerate() {
+            commands.spawn((
+                ParallaxLayer { config: i, camera },
+                parallax_render_layer(*slot),
+                Sprite {
+                    image: image.clone(),
+                    color: layer.color,
+                    image_mode: SpriteImageMode::Tiled {
+                        tile_x: true,
+                        tile_y: false,
+                        stretch_value: 1.,
+                    },
+                    ..default()
+                },
+                Transform::from_xyz(0., 0., layer.depth),
+                Name::new("Parallax layer"),
+            ));
+        }
+    }
+}
+
+/// Removes the backgrounds of cameras that went away
+pub fn despawn_parallax_layers(
+    mut commands: Commands,
+    layers: Query<(Entity, &ParallaxLayer)>,
+    cameras: Query<(), With<GameCamera>>,
+) {
+    for (entity, layer) in &layers {
+        if !cameras.contains(layer.camera) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Swaps in the background variant that fits the canvas as it is scaled up in
+/// the window, the canvas itself is always the same size
+pub fn pick_background_variant(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut variant: ResMut<BackgroundVariant>,
+    mut layers: Query<&mut Sprite, With<ParallaxLayer>>,
+    asset_server: Res<AssetServer>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    let height = displayed_canvas_height(window.physical_size());
+    let fitting = BackgroundVariant(variant_for_height(height as f32));
+    if variant.set_if_neq(fitting) {
+        let (path, _) = BACKGROUND_VARIANTS[variant.0];
+        info!("Using background {}", path);
+        let image = asset_server.load(path);
+        for mut sprite in &mut layers {
+            sprite.image = image.clone();
+        }
+    }
+}
+
+/// Keeps the layers covering the view of their camera, scrolled by their share
+/// of the camera movement
+pub fn scroll_parallax_layers(
+    cameras: Query<
+        (&Transform, &OrthographicProjection),
+        (With<GameCamera>, Without<ParallaxLayer>),
+    >,
+    mut layers: Query<(&ParallaxLayer, &mut Transform, &mut Sprite)>,
+    variant: Res<BackgroundVariant>,
+) {
+    let (_, texture_height) = BACKGROUND_VARIANTS[variant.0];
+
+    for (layer, mut transform, mut sprite) in &mut layers {
+        let Ok((camera, projection)) = cameras.get(layer.camera) else {
+            continue;
+        };
+        let view = projection.area.size();
+        let camera_position = camera.translation.truncate();
+        let config = &PARALLAX_LAYERS[layer.config];
+        let height = view.y * config.scale;
+        let tile_width = height * BACKGROUND_ASPECT;
+        // Whole tiles, with one to spare on each side to scroll into
+        let tiles = (view.x / tile_width).ceil() + 2.;
+        sprite.custom_size = Some(Vec2::new(tiles * tile_width, height));
+        if let SpriteImageMode::Tiled { stretch_value, .. } = &mut sprite.image_mode {
+            *stretch_value = height / texture_height;
+        }
+
+        let scrolled = camera_position * config.scroll;
+        // Horizontally the tiles wrap around, vertically the layer only has its
+        // extra height to move in
+        let slack = (height - view.y).max(0.) / 2.;
+        let offset = Vec2::new(
+            -scrolled.x.rem_euclid(tile_width) + tile_width / 2.,
+            (-scrolled.y).clamp(-slack, slack),
+        );
+        let position = camera_position + offset;
+        transform.translation = position.extend(config.depth);
+    }
+}
+
+/// Scrolling backgrounds behind the levels
+pub struct ParallaxPlugin;
+
+impl Plugin for ParallaxPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BackgroundVariant>().add_systems(
+            PostUpdate,
+            (
+                spawn_parallax_layers,
+                despawn_parallax_layers,
+                pick_background_variant,
+                scroll_parallax_layers,
+            )
+                .chain()
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{RenderTarget, Viewport},
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        view::RenderLayers,
+    },
+    window::PrimaryWindow,
+};
+
+/// Width over height of what every player sees
+pub const ASPECT_RATIO: f32 = 4. / 3.;
+/// Height of the view in world pixels, a whole level
+pub const VIRTUAL_HEIGHT: u32 = 176;
+pub const VIRTUAL_WIDTH: u32 = (VIRTUAL_HEIGHT as f32 * ASPECT_RATIO) as u32;
+
+/// Layer of the canvas sprite, the only thing the window camera draws
+const CANVAS_LAYER: RenderLayers = RenderLayers::layer(1);
+/// Every game camera has its own parallax background on the layer after this plus its slot
+const PARALLAX_LAYER_BASE: usize = 2;
+
+/// Camera drawing the game into the low resolution canvas
+#[derive(Component)]
+pub struct GameCamera;
+
+/// Which of the game cameras this is, there is more than one in split screen
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CameraSlot(pub usize);
+
+/// What every game camera renders into
+#[derive(Resource)]
+pub struct CanvasImage(pub Handle<Image>);
+
+/// Layer of the parallax background seen by the game camera in `slot`
+pub fn parallax_render_layer(slot: CameraSlot) -> RenderLayers {
+    RenderLayers::layer(PARALLAX_LAYER_BASE + slot.0)
+}
+
+/// A game camera rendering into the canvas, or part of it with a `viewport`.
+/// The first one clears the canvas for the others.
+pub fn game_camera(
+    slot: CameraSlot,
+    canvas: Handle<Image>,
+    viewport: Option<Viewport>,
+) -> impl Bundle {
+    (
+        Camera2d,
+        Camera {
+            order: -16 + slot.0 as isize,
+            target: RenderTarget::Image(canvas),
+            viewport,
+            clear_color: if slot.0 == 0 {
+                ClearColorConfig::Default
+            } else {
+                ClearColorConfig::None
+            },
+            ..default()
+        },
+        Msaa::Off,
+        GameCamera,
+        slot,
+        RenderLayers::layer(0).with(PARALLAX_LAYER_BASE + slot.0),
+        Name::new(format!("Game camera {}", slot.0)),
+    )
+}
+
+/// Camera drawing the canvas into the window
+#[derive(Component)]
+pub struct WindowCamera;
+
+/// Sprite showing the canvas
+#[derive(Component)]
+pub struct Canvas;
+
+pub fn spawn_cameras(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let size = Extent3d {
+        width: VIRTUAL_WIDTH,
+        height: VIRTUAL_HEIGHT,
+        ..default()
+    };
+    let mut canvas = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("canvas"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    canvas.resize(size);
+    let canvas = images.add(canvas);
+    commands.insert_resource(CanvasImage(canvas.clone()));
+
+    commands.spawn(game_camera(CameraSlot(0), canvas.clone(), None));
+
+    commands.spawn((Sprite::from_image(canvas), Canvas, CANVAS_LAYER));
+    commands.spawn((
+        Camera2d,
+        Camera {
+            // Letterbox bars
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+            ..default()
+        },
+        Msaa::Off,
+        WindowCamera,
+        CANVAS_LAYER,
+        Name::new("Window camera"),
+    ));
+}
+
+/// The biggest whole number of window pixels per canvas pixel that fits
+fn integer_scale(physical_size: UVec2) -> u32 {
+    (physical_size.x / VIRTUAL_WIDTH)
+        .min(physical_size.y / VIRTUAL_HEIGHT)
+        .max(1)
+}
+
+/// Physical height the canvas is shown at in a window of `physical_size`
+pub fn displayed_canvas_height(physical_size: UVec2) -> u32 {
+    VIRTUAL_HEIGHT * integer_scale(physical_size)
+}
+
+/// Scales the canvas up by a whole number, whatever is left of the window is letterboxed
+pub fn fit_canvas(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut projections: Query<&mut OrthographicProjection, With<WindowCamera>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    // The projection works in logical pixels
+    let scale = window.scale_factor() / integer_scale(window.physical_size()) as f32;
+    for mut projection in &mut projections {
+        if projection.scale != scale {
+            projection.scale = scale;
+        }
+    }
+}
+
+/// Where a point seen by a game camera ends up in the window, in logical pixels from the
+/// top left. `None` when the camera doesn't see it.
+pub fn world_to_window(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    window: &Window,
+    window_projection: &OrthographicProjection,
+    point: Vec3,
+) -> Option<Vec2> {
+    let ndc = camera.world_to_ndc(camera_transform, point)?;
+    if ndc.x.abs() > 1. || ndc.y.abs() > 1. {
+        return None;
+    }
+    // The canvas is an image, one logical pixel is one canvas pixel and y points down
+    let viewport = camera.logical_viewport_rect()?;
+    let on_canvas = viewport.min + Vec2::new(ndc.x + 1., 1. - ndc.y) / 2. * viewport.size();
+    let canvas_size = Vec2::new(VIRTUAL_WIDTH as f32, VIRTUAL_HEIGHT as f32);
+    // The canvas sprite is centered in the window camera's view
+    Some(window.size() / 2. + (on_canvas - canvas_size / 2.) / window_projection.scale)
+}
+
+/// Renders the game at a fixed low resolution, scaled up without blurring,
+/// so every player sees the same area
+pub struct PixelPerfectPlugin;
+
+impl Plugin for PixelPerfectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_cameras)
+            .add_systems(Update, fit_canvas);
+    }
+}
@@ -0,0 +1,283 @@
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{prelude::*, utils::*};
+use bevy_ggrs::{Rollback, RollbackFrameCount};
+
+use crate::config::MOVING_PLATFORM_ENTITY;
+
+use super::{
+    controller::{MovementAction, MovementEvent},
+    levels::level_bounds,
+    multiplayer::{TARGET_FPS, handshake::RollbackRegistryExt},
+    player::Player,
+    walls::{Surface, WallSurface},
+};
+
+/// How long dropping through ignores the platforms, enough to fall clear of them
+const DROP_THROUGH_FRAMES: i32 = 12;
+/// How deep feet can sink into a platform and still land on it
+const LANDING_TOLERANCE: f32 = 4.0;
+
+/// Collider of merged one-way platform tiles
+#[derive(Component)]
+pub struct OneWayPlatform;
+
+/// Players ignore one-way platforms until this frame, after pressing down and jump
+#[derive(Component, Default, Clone)]
+pub struct DropThrough {
+    pub until_frame: i32,
+}
+
+/// Whether a player should pass through a one-way platform instead of standing on it.
+///
+/// Only depends on rolled back state, so every peer decides the same.
+pub fn passes_through(
+    player_bottom: f32,
+    platform_top: f32,
+    velocity: &LinearVelocity,
+    drop_through: &DropThrough,
+    frame: i32,
+) -> bool {
+    frame < drop_through.until_frame
+        || velocity.y > 0.
+        || player_bottom < platform_top - LANDING_TOLERANCE
+}
+
+pub fn start_drop_through(
+    mut movement_events: EventReader<MovementEvent>,
+    mut players: Query<(&Player, &mut DropThrough), With<Rollback>>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let frame: i32 = (*frame).into();
+    for event in movement_events.read() {
+        let dropping = event
+            .actions
+            .iter()
+            .any(|action| matches!(action, MovementAction::DropThrough(_)));
+        if !dropping {
+            continue;
+        }
+        for (player, mut drop_through) in &mut players {
+            if player.handle == event.handle {
+                drop_through.until_frame = frame + DROP_THROUGH_FRAMES;
+            }
+        }
+    }
+}
+
+/// Throws away contacts between players and the one-way platforms they pass through,
+/// before anything gets to respond to them
+pub fn filter_one_way_platforms(
+    mut collisions: ResMut<Collisions>,
+    players: Query<
+        (
+            &Collider,
+            &Position,
+            &Rotation,
+            &LinearVelocity,
+            &DropThrough,
+        ),
+        With<Player>,
+    >,
+    platforms: Query<(&Collider, &Position, &Rotation), With<OneWayPlatform>>,
+    frame: Res<RollbackFrameCount>,
+) {
+    let frame: i32 = (*frame).into();
+    collisions.retain(|contacts| {
+        let (player, platform) = if players.contains(contacts.entity1) {
+            (contacts.entity1, contacts.entity2)
+        } else {
+            (contacts.entity2, contacts.entity1)
+        };
+        let (
+            Ok((player_collider, player_position, player_rotation, velocity, drop_through)),
+            Ok((platform_collider, platform_position, platform_rotation)),
+        ) = (players.get(player), platforms.get(platform))
+        else {
+            return true;
+        };
+
+        let player_bottom = player_collider
+            .aabb(player_position.0, *player_rotation)
+            .min
+            .y;
+        let platform_top = platform_collider
+            .aabb(platform_position.0, *platform_rotation)
+            .max
+            .y;
+        !passes_through(player_bottom, platform_top, velocity, drop_through, frame)
+    });
+}
+
+/// A platform going back and forth along a path from the level, waiting at every point
+#[derive(Component, Clone, Debug)]
+pub struct MovingPlatform {
+    /// Uid of the level the path is relative to,
+    /// streamed worlds have platforms in more than one level
+    pub level_uid: i32,
+    /// Level space points, starting where the platform was placed
+    pub path: Vec<Vec2>,
+    /// Pixels per second
+    pub speed: f32,
+    /// Seconds spent at every point
+    pub wait: f32,
+}
+
+impl MovingPlatform {
+    /// Level space position `time` seconds into the match.
+    ///
+    /// The platform only depends on time, so the rollback frame count is all
+    /// the state it needs and it can't drift apart between peers.
+    pub fn position_at(&self, time: f32) -> Vec2 {
+        // There and back again: 0, 1, .., n, n - 1, .., 1
+        let stops: Vec<Vec2> = self
+            .path
+            .iter()
+            .chain(
+                self.path
+                    .iter()
+                    .rev()
+                    .skip(1)
+                    .take(self.path.len().saturating_sub(2)),
+            )
+            .copied()
+            .collect();
+        if stops.len() < 2 || self.speed <= 0. {
+            return self.path.first().copied().unwrap_or_default();
+        }
+
+        let legs: Vec<(Vec2, Vec2)> = stops
+            .iter()
+            .zip(stops.iter().cycle().skip(1))
+            .map(|(from, to)| (*from, *to))
+            .collect();
+        let cycle: f32 = legs
+            .iter()
+            .map(|(from, to)| self.wait + from.distance(*to) / self.speed)
+            .sum();
+        if cycle <= 0. {
+            return stops[0];
+        }
+
+        let mut time = time.rem_euclid(cycle);
+        for (from, to) in legs {
+            if time < self.wait {
+                return from;
+            }
+            time -= self.wait;
+            let travel = from.distance(to) / self.speed;
+            if time < travel {
+                return from.lerp(to, time / travel);
+            }
+            time -= travel;
+        }
+        stops[0]
+    }
+}
+
+/// Kinematic body carrying players along
+#[derive(Bundle)]
+pub struct MovingPlatformBundle {
+    moving_platform: MovingPlatform,
+    sprite: Sprite,
+    collider: Collider,
+    rigid_body: RigidBody,
+    friction: Friction,
+    surface: WallSurface,
+}
+
+impl LdtkEntity for MovingPlatformBundle {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> Self {
+        let size = IVec2::new(entity_instance.width, entity_instance.height);
+        let grid_size = layer_instance.grid_size;
+        let start = ldtk_pixel_coords_to_translation_pivoted(
+            entity_instance.px,
+            layer_instance.c_hei * grid_size,
+            size,
+            entity_instance.pivot,
+        );
+        let points = entity_instance
+            .get_points_field("Path")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|point| {
+                ldtk_grid_coords_to_translation(
+                    *point,
+                    layer_instance.c_hei,
+                    IVec2::splat(grid_size),
+                )
+            });
+        let size = size.as_vec2();
+
+        MovingPlatformBundle {
+            moving_platform: MovingPlatform {
+                level_uid: layer_instance.level_id,
+                path: std::iter::once(start).chain(points).collect(),
+                speed: *entity_instance.get_float_field("Speed").unwrap_or(&0.),
+                wait: *entity_instance.get_float_field("Wait").unwrap_or(&0.),
+            },
+            sprite: Sprite::from_color(Color::srgb(0.58, 0.69, 0.76), size),
+            collider: Collider::rectangle(size.x, size.y),
+            rigid_body: RigidBody::Kinematic,
+            friction: Friction::new(1.0),
+            surface: WallSurface(Surface::Normal),
+        }
+    }
+}
+
+/// Puts moving platforms where they are on this frame, with the velocity
+/// that takes them to where they are on the next one.
+///
+/// Where their level is comes from the project, so it doesn't depend on
+/// when the level finished spawning on this peer.
+pub fn move_platforms(
+    mut platforms: Query<(&MovingPlatform, &mut Position, &mut LinearVelocity)>,
+    frame: Res<RollbackFrameCount>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let Some(project) = ldtk_project_assets.get(ldtk_projects.single()) else {
+        return;
+    };
+    let frame: i32 = (*frame).into();
+    let time = |frame: i32| frame as f32 / TARGET_FPS as f32;
+
+    for (platform, mut position, mut velocity) in &mut platforms {
+        let Some(level_offset) = project
+            .json_data()
+            .levels
+            .iter()
+            .find(|level| level.uid == platform.level_uid)
+            .map(|level| level_bounds(level).min)
+        else {
+            continue;
+        };
+        let now = platform.position_at(time(frame)) + level_offset;
+        let next = platform.position_at(time(frame + 1)) + level_offset;
+        position.0 = now;
+        velocity.0 = (next - now) * TARGET_FPS as f32;
+    }
+}
+
+/// Platforms players jump onto from below and drop through with down and jump,
+/// and platforms moving along a path.
+///
+/// Moving platforms aren't rolled back. Where they are is a function of the
+/// rollback frame count and the project alone, so every peer computes the same
+/// position for every frame, resimulated ones included.
+pub struct PlatformPlugin;
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_rollback_component::<DropThrough>()
+            .register_ldtk_entity::<MovingPlatformBundle>(MOVING_PLATFORM_ENTITY)
+            .add_systems(PostProcessCollisions, filter_one_way_platforms);
+    }
+}
@@ -2,15 +2,21 @@ pub mod camera;
 pub mod movement;
 pub mod spawn;
 
-use avian2d::prelude::SleepingDisabled;
+use avian2d::prelude::{CollidingEntities, SleepingDisabled};
 pub use spawn::*;
 
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 
-use crate::game::GameState;
+use crate::{
+    config::{PLAYER_ENTITY, SPAWN_POINT_ENTITY},
+    game::GameState,
+};
 
-use super::controller::CharacterControllerBundle;
+use super::{
+    checkpoints::RespawnPoint, controller::CharacterControllerBundle, interactibles::UseHeld,
+    platforms::DropThrough,
+};
 
 #[derive(Default, Component)]
 pub struct Player {
@@ -23,6 +29,10 @@ pub struct PlayerBundle {
 
     pub character_controller: CharacterControllerBundle,
     pub no_sleep: SleepingDisabled,
+    pub colliding_entities: CollidingEntities,
+    pub respawn_point: RespawnPoint,
+    pub use_held: UseHeld,
+    pub drop_through: DropThrough,
 
     #[sprite_sheet]
     pub sprite_sheet: Sprite,
@@ -34,9 +44,15 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Update, movement::move_players)
-            .add_event::<SpawnPlayerEvent>()
-            .register_ldtk_entity::<SpawnPointBundle>("SpawnPoint")
-            .add_systems(Last, spawn_player.run_if(in_state(GameState::Playing)))
-            .register_ldtk_entity::<PlayerBundle>("Player");
+            .init_resource::<PlayerSpawnState>()
+            .init_resource::<camera::CameraSettings>()
+            .init_resource::<camera::SplitScreen>()
+            .add_systems(OnExit(GameState::InGame), camera::reset_cameras)
+            .register_ldtk_entity::<SpawnPointBundle>(SPAWN_POINT_ENTITY)
+            .add_systems(
+                Update,
+                (check_spawn_points, show_spawn_error).run_if(in_state(GameState::InGame)),
+            )
+            .register_ldtk_entity::<PlayerBundle>(PLAYER_ENTITY);
     }
 }
@@ -1,27 +1,349 @@
-use bevy::{prelude::*, render::camera};
+use avian2d::prelude::*;
+use bevy::{prelude::*, render::camera::Viewport};
 use bevy_ecs_ldtk::prelude::*;
-use bevy_ggrs::LocalPlayers;
 
-use crate::systems::multiplayer::Local;
+use crate::systems::{
+    death::Dead,
+    levels::{LevelProgress, find_level, level_bounds},
+    multiplayer::Local,
+    pixel_perfect::{
+        CameraSlot, CanvasImage, GameCamera, VIRTUAL_HEIGHT, VIRTUAL_WIDTH, game_camera,
+    },
+    streaming::is_streamed,
+};
 
 use super::Player;
 
-const ASPECT_RATIO: f32 = 4. / 3.;
-const PLAYER_EDGE: f32 = -10.;
+/// How the cameras follow the local players
+#[derive(Resource)]
+pub struct CameraSettings {
+    /// How quickly the camera catches up, per second
+    pub damping: f32,
+    /// How far ahead of the player the camera looks when running at `look_ahead_speed`
+    pub look_ahead: Vec2,
+    pub look_ahead_speed: f32,
+    /// Half size of the area the player moves in without moving the camera
+    pub deadzone: Vec2,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            damping: 5.,
+            look_ahead: Vec2::new(24., 0.),
+            look_ahead_speed: 20.,
+            deadzone: Vec2::new(8., 12.),
+        }
+    }
+}
 
-// Stores global coordinates for bottom left and top right corners of a camera view
+/// Point the camera is centered on before looking ahead, dragged along by the
+/// player at the edges of the deadzone
 #[derive(Component, Default)]
-struct CameraView(Vec2, Vec2);
+pub struct CameraFocus(Vec2);
+
+/// Bounds of the level the camera stays in. Levels played one after another only
+/// have the current one loaded, streamed levels the one the point is in.
+fn level_around(project: &LdtkProject, progress: &LevelProgress, point: Vec2) -> Option<Rect> {
+    let current = || find_level(project, progress.level_iid()).map(level_bounds);
+    if !is_streamed(project) {
+        return current();
+    }
+    project
+        .json_data()
+        .levels
+        .iter()
+        .map(level_bounds)
+        .find(|bounds| bounds.contains(point))
+        .or_else(current)
+}
+
+/// Keeps a view of `view` size centered near `center` inside `bounds`,
+/// levels smaller than the view get centered
+fn clamp_to_bounds(center: Vec2, view: Vec2, bounds: Rect) -> Vec2 {
+    let min = bounds.min + view / 2.;
+    let max = bounds.max - view / 2.;
+    Vec2::new(
+        if min.x <= max.x {
+            center.x.clamp(min.x, max.x)
+        } else {
+            bounds.center().x
+        },
+        if min.y <= max.y {
+            center.y.clamp(min.y, max.y)
+        } else {
+            bounds.center().y
+        },
+    )
+}
+
+/// How far the shared camera zooms out to keep every local player in view,
+/// beyond that the screen gets split
+const MAX_ZOOM: f32 = 1.5;
+/// How close the local players have to come again to share a view after splitting.
+/// Below `MAX_ZOOM` so players moving around the limit don't flip back and forth.
+const MERGE_ZOOM: f32 = 1.3;
+/// Room kept around the local players in the shared view
+const SHARED_MARGIN: f32 = 24.;
+
+/// Handles of the local players a game camera keeps in view
+#[derive(Component, Default, Clone, PartialEq)]
+pub struct FollowedPlayers(pub Vec<usize>);
+
+/// How the screen is split between the local players, kept while split so
+/// players don't swap viewports when they pass each other
+#[derive(Resource, Default)]
+pub struct SplitScreen {
+    /// Local player handles by camera slot, empty while they share one view
+    order: Vec<usize>,
+    side_by_side: bool,
+}
+
+/// Part of the canvas a game camera draws, with the players it follows
+struct CameraLayout {
+    viewport: Option<Viewport>,
+    players: Vec<usize>,
+    zoom: f32,
+}
+
+/// One view zoomed out to fit every local player, or when they are too far apart
+/// a viewport each, side by side or stacked depending on how they were spread out
+/// when the screen got split
+fn layout_cameras(players: &[(usize, Vec2)], split: &mut SplitScreen) -> Vec<CameraLayout> {
+    let canvas = UVec2::new(VIRTUAL_WIDTH, VIRTUAL_HEIGHT);
+    let all = || players.iter().map(|(handle, _)| *handle).collect();
+    if players.len() < 2 {
+        split.order.clear();
+        return vec![CameraLayout {
+            viewport: None,
+            players: all(),
+            zoom: 1.,
+        }];
+    }
+
+    let min = players
+        .iter()
+        .fold(Vec2::MAX, |min, (_, position)| min.min(*position));
+    let max = players
+        .iter()
+        .fold(Vec2::MIN, |max, (_, position)| max.max(*position));
+    let spread = (max - min + 2. * SHARED_MARGIN) / canvas.as_vec2();
+    let zoom = spread.max_element().max(1.);
+
+    // Players joining or leaving split the screen anew
+    let still_split = split.order.len() == players.len()
+        && players
+            .iter()
+            .all(|(handle, _)| split.order.contains(handle));
+    let limit = if still_split { MERGE_ZOOM } else { MAX_ZOOM };
+    if zoom <= limit {
+        split.order.clear();
+        return vec![CameraLayout {
+            viewport: None,
+            players: all(),
+            zoom,
+        }];
+    }
+
+    if !still_split {
+        split.side_by_side = spread.x >= spread.y;
+        let mut sorted = players.to_vec();
+        // Leftmost player on the left, topmost player on top
+        if split.side_by_side {
+            sorted.sort_by(|(_, a), (_, b)| a.x.total_cmp(&b.x));
+        } else {
+            sorted.sort_by(|(_, a), (_, b)| b.y.total_cmp(&a.y));
+        }
+        split.order = sorted.into_iter().map(|(handle, _)| handle).collect();
+    }
 
+    let count = split.order.len() as u32;
+    split
+        .order
+        .iter()
+        .enumerate()
+        .map(|(i, handle)| {
+            let i = i as u32;
+            let (physical_position, physical_size) = if split.side_by_side {
+                let width = canvas.x / count;
+                (UVec2::new(i * width, 0), UVec2::new(width, canvas.y))
+            } else {
+                let height = canvas.y / count;
+                (UVec2::new(0, i * height), UVec2::new(canvas.x, height))
+            };
+            CameraLayout {
+                viewport: Some(Viewport {
+                    physical_position,
+                    physical_size,
+                    ..default()
+                }),
+                players: vec![*handle],
+                zoom: 1.,
+            }
+        })
+        .collect()
+}
+
+fn same_viewport(a: &Option<Viewport>, b: &Option<Viewport>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.physical_position == b.physical_position && a.physical_size == b.physical_size
+        }
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// Shares the screen between the local players, adding and removing game cameras
+/// when switching to and from split screen
+#[allow(clippy::type_complexity)]
+pub fn arrange_cameras(
+    mut commands: Commands,
+    players: Query<(&Player, &Position), (With<Local>, Without<Dead>)>,
+    mut cameras: Query<
+        (
+            Entity,
+            &CameraSlot,
+            &mut Camera,
+            &mut OrthographicProjection,
+            Option<&mut FollowedPlayers>,
+        ),
+        With<GameCamera>,
+    >,
+    mut split: ResMut<SplitScreen>,
+    canvas: Res<CanvasImage>,
+) {
+    let mut positions: Vec<(usize, Vec2)> = players
+        .iter()
+        .map(|(player, position)| (player.handle, position.0))
+        .collect();
+    positions.sort_by_key(|(handle, _)| *handle);
+    let layouts = layout_cameras(&positions, &mut split);
+
+    for (entity, slot, mut camera, mut projection, followed) in &mut cameras {
+        let Some(layout) = layouts.get(slot.0) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        if !same_viewport(&camera.viewport, &layout.viewport) {
+            camera.viewport = layout.viewport.clone();
+        }
+        if projection.scale != layout.zoom {
+            projection.scale = layout.zoom;
+        }
+        let players = FollowedPlayers(layout.players.clone());
+        match followed {
+            Some(mut followed) => {
+                followed.set_if_neq(players);
+            }
+            None => {
+                commands.entity(entity).insert(players);
+            }
+        }
+    }
+
+    for (i, layout) in layouts.iter().enumerate().skip(cameras.iter().count()) {
+        commands.spawn((
+            game_camera(CameraSlot(i), canvas.0.clone(), layout.viewport.clone()),
+            FollowedPlayers(layout.players.clone()),
+        ));
+    }
+}
+
+/// Moves every game camera after the players it follows
+#[allow(clippy::type_complexity)]
 pub fn camera_follow_local_players(
-    player_locations_q: Query<(&GlobalTransform), (With<Player>, With<Local>)>,
-    mut camera_q: Query<(&mut Transform), (With<Camera2d>, Without<Player>)>,
+    mut commands: Commands,
+    player_locations_q: Query<(&Player, &Position, &LinearVelocity), (With<Local>, Without<Dead>)>,
+    mut camera_q: Query<
+        (
+            Entity,
+            &FollowedPlayers,
+            &mut Transform,
+            &OrthographicProjection,
+            Option<&mut CameraFocus>,
+        ),
+        (With<GameCamera>, Without<Player>),
+    >,
+    settings: Res<CameraSettings>,
+    progress: Res<LevelProgress>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+    time: Res<Time>,
+) {
+    let project = ldtk_project_assets.get(ldtk_projects.single());
+
+    for (camera, followed, mut camera_transform, projection, focus) in &mut camera_q {
+        let (positions, velocities): (Vec<Vec2>, Vec<Vec2>) = player_locations_q
+            .iter()
+            .filter(|(player, ..)| followed.0.contains(&player.handle))
+            .map(|(_, position, velocity)| (position.0, velocity.0))
+            .unzip();
+        if positions.is_empty() {
+            continue;
+        }
+        let count = positions.len() as f32;
+        let position = positions.iter().sum::<Vec2>() / count;
+        let velocity = velocities.iter().sum::<Vec2>() / count;
+        let view = projection.area.size();
+
+        // The players only drag the focus along once they leave the deadzone,
+        // new cameras start out on them
+        let mut focus_point = focus.as_ref().map_or(position, |focus| focus.0);
+        let from_focus = position - focus_point;
+        focus_point += from_focus - from_focus.clamp(-settings.deadzone, settings.deadzone);
+
+        let look_ahead = (velocity / settings.look_ahead_speed).clamp(Vec2::NEG_ONE, Vec2::ONE)
+            * settings.look_ahead;
+        let mut target = focus_point + look_ahead;
+        if let Some(bounds) = project.and_then(|project| level_around(project, &progress, position))
+        {
+            target = clamp_to_bounds(target, view, bounds);
+        }
+
+        let current = camera_transform.translation.truncate();
+        // Spawns and level changes cut to the players instead of panning across
+        let next = if focus.is_none() || current.distance(target) > view.length() {
+            focus_point = position;
+            target
+        } else {
+            current.lerp(target, 1. - (-settings.damping * time.delta_secs()).exp())
+        };
+        camera_transform.translation = next.extend(camera_transform.translation.z);
+
+        match focus {
+            Some(mut focus) => focus.0 = focus_point,
+            None => {
+                commands.entity(camera).insert(CameraFocus(focus_point));
+            }
+        }
+    }
+}
+
+/// Leaves a single game camera over the whole canvas after a match, split screen
+/// cameras would stay on screen in the menus
+pub fn reset_cameras(
+    mut commands: Commands,
+    mut cameras: Query<
+        (
+            Entity,
+            &CameraSlot,
+            &mut Camera,
+            &mut OrthographicProjection,
+        ),
+        With<GameCamera>,
+    >,
+    mut split: ResMut<SplitScreen>,
 ) {
-    let Ok(mut camera_transform) = camera_q.get_single_mut() else {
-        return;
-    };
-    let Ok(player) = player_locations_q.get_single() else {
-        return;
-    };
-    camera_transform.translation = player.translation();
+    split.order.clear();
+    for (entity, slot, mut camera, mut projection) in &mut cameras {
+        if slot.0 != 0 {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        camera.viewport = None;
+        projection.scale = 1.;
+        commands
+            .entity(entity)
+            .remove::<(FollowedPlayers, CameraFocus)>();
+    }
 }
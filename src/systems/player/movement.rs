@@ -1,17 +1,24 @@
-use crate::systems::controller::{
-    CharacterController, Grounded, JumpPower, MaxSlopeAngle, MovementAcceleration, MovementAction,
-    MovementDampingFactor, MovementEvent,
+use crate::systems::{
+    controller::{
+        CharacterController, GroundSurface, GroundVelocity, Grounded, JumpPower, MaxSlopeAngle,
+        MovementAcceleration, MovementAction, MovementDampingFactor, MovementEvent,
+        OnOneWayPlatform,
+    },
+    platforms::{DropThrough, MovingPlatform, OneWayPlatform, passes_through},
+    walls::{Surface, WallSurface},
 };
 use avian2d::{
     math::{Scalar, Vector},
     prelude::*,
 };
 use bevy::prelude::*;
-use bevy_ggrs::{GgrsTime, Rollback};
+use bevy_ggrs::{GgrsTime, Rollback, RollbackFrameCount};
 
 use super::Player;
 
 const MOVE_SPEED: f32 = 20.0;
+/// Vertical speed a bounce pad launches characters with, about twice a jump
+const BOUNCE_SPEED: f32 = 80.0;
 
 pub fn move_players(
     mut players: Query<
@@ -39,6 +46,7 @@ pub fn move_players(
                 MovementAction::Jump(handle) => {
                     jumps.push(*handle);
                 }
+                MovementAction::Interact(_) | MovementAction::DropThrough(_) => {}
             }
         }
     }
@@ -63,11 +71,11 @@ pub fn move_players(
         // For some reason, if you wanted to zero out a velocity and it happens
         // that the two players are in contact, it will cause a desync in Avian
         let new_vel_x = if horizontal != 0. {
-           horizontal
+            horizontal
         } else {
-           velocity.x
+            velocity.x
         };
-        
+
         let new_vel_y = if vertical != 0. { vertical } else { velocity.y };
 
         velocity.x = new_vel_x;
@@ -75,13 +83,46 @@ pub fn move_players(
     }
 }
 
-/// Slows down movement in the X direction.
+/// Slows down movement in the X direction, less on ice and more in mud.
 pub fn apply_movement_damping(
-    mut query: Query<(&MovementDampingFactor, &mut LinearVelocity), With<Rollback>>,
-    time: Res<Time<GgrsTime>>
+    mut query: Query<
+        (
+            &MovementDampingFactor,
+            Option<&GroundSurface>,
+            &mut LinearVelocity,
+        ),
+        With<Rollback>,
+    >,
+    time: Res<Time<GgrsTime>>,
 ) {
-    for (damping_factor, mut linear_velocity) in &mut query {
-        linear_velocity.x *= 1.0 - (damping_factor.0 * time.delta_secs());
+    for (damping_factor, surface, mut linear_velocity) in &mut query {
+        let scale = surface.map_or(1.0, |surface| surface.0.damping_scale());
+        linear_velocity.x *= 1.0 - (damping_factor.0 * scale * time.delta_secs());
+    }
+}
+
+/// Launches characters standing on a bounce pad
+pub fn bounce_on_pads(
+    mut query: Query<
+        (&GroundSurface, &mut LinearVelocity),
+        (With<CharacterController>, With<Rollback>),
+    >,
+) {
+    for (surface, mut linear_velocity) in &mut query {
+        if surface.0 == Surface::Bouncy && linear_velocity.y <= 0. {
+            linear_velocity.y = BOUNCE_SPEED;
+        }
+    }
+}
+
+/// Moves characters along with the moving platform they stand on,
+/// on top of their own velocity
+pub fn carry_on_platforms(
+    mut query: Query<(&GroundVelocity, &mut Position), (With<CharacterController>, With<Rollback>)>,
+    time: Res<Time<GgrsTime>>,
+) {
+    for (ground_velocity, mut position) in &mut query {
+        position.0 += ground_velocity.0 * time.delta_secs();
     }
 }
 
@@ -99,28 +140,89 @@ pub fn apply_gravity(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_grounded(
     mut commands: Commands,
     mut query: Query<
-        (Entity, &ShapeHits, &Rotation, Option<&MaxSlopeAngle>),
+        (
+            Entity,
+            &ShapeHits,
+            &Collider,
+            &Position,
+            &Rotation,
+            &LinearVelocity,
+            Option<&DropThrough>,
+            Option<&MaxSlopeAngle>,
+        ),
         (With<CharacterController>, With<Rollback>),
     >,
+    platforms: Query<(&Collider, &Position, &Rotation), With<OneWayPlatform>>,
+    surfaces: Query<&WallSurface>,
+    moving_platforms: Query<&LinearVelocity, With<MovingPlatform>>,
+    frame: Res<RollbackFrameCount>,
 ) {
-    for (entity, hits, rotation, max_slope_angle) in &mut query {
+    let frame: i32 = (*frame).into();
+    for (entity, hits, collider, position, rotation, velocity, drop_through, max_slope_angle) in
+        &mut query
+    {
+        let bottom = collider.aabb(position.0, *rotation).min.y;
         // The character is grounded if the shape caster has a hit with a normal
         // that isn't too steep.
-        let is_grounded = hits.iter().any(|hit| {
-            if let Some(angle) = max_slope_angle {
-                (rotation * -hit.normal2).angle_to(Vector::Y).abs() <= angle.0
+        let ground: Vec<_> = hits
+            .iter()
+            .filter(|hit| {
+                // One-way platforms only hold up characters that are landing on them
+                if let Ok((platform, platform_position, platform_rotation)) =
+                    platforms.get(hit.entity)
+                {
+                    let top = platform.aabb(platform_position.0, *platform_rotation).max.y;
+                    let drop_through = drop_through.cloned().unwrap_or_default();
+                    if passes_through(bottom, top, velocity, &drop_through, frame) {
+                        return false;
+                    }
+                }
+                if let Some(angle) = max_slope_angle {
+                    (rotation * -hit.normal2).angle_to(Vector::Y).abs() <= angle.0
+                } else {
+                    true
+                }
+            })
+            .collect();
+        // Hits come in no particular order, when standing on several surfaces
+        // the lowest `Surface` wins so that every peer picks the same one
+        let surface = ground
+            .iter()
+            .map(|hit| {
+                surfaces
+                    .get(hit.entity)
+                    .map(|surface| surface.0)
+                    .unwrap_or_default()
+            })
+            .min();
+        // Same for platforms, the fastest one by its components wins
+        let ground_velocity = ground
+            .iter()
+            .filter_map(|hit| moving_platforms.get(hit.entity).ok())
+            .map(|velocity| velocity.0)
+            .max_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)))
+            .unwrap_or_default();
+        let on_one_way_platform = ground.iter().all(|hit| platforms.contains(hit.entity));
+
+        if let Some(surface) = surface {
+            commands.entity(entity).insert((
+                Grounded,
+                GroundSurface(surface),
+                GroundVelocity(ground_velocity),
+            ));
+            if on_one_way_platform {
+                commands.entity(entity).insert(OnOneWayPlatform);
             } else {
-                true
+                commands.entity(entity).remove::<OnOneWayPlatform>();
             }
-        });
-
-        if is_grounded {
-            commands.entity(entity).insert(Grounded);
         } else {
-            commands.entity(entity).remove::<Grounded>();
+            commands
+                .entity(entity)
+                .remove::<(Grounded, GroundSurface, GroundVelocity, OnOneWayPlatform)>();
         }
     }
 }
@@ -1,115 +1,136 @@
+use std::fmt;
+
 use bevy::prelude::*;
-use bevy_ecs_ldtk::prelude::*;
-use bevy_ecs_ldtk::utils::ldtk_pixel_coords_to_translation_pivoted;
+use bevy_ecs_ldtk::{ldtk::Level, prelude::*};
+use bevy_egui::{EguiContexts, egui};
 
-use crate::config::PLAYER_Z;
+use crate::{
+    config::SPAWN_POINT_ENTITY,
+    systems::levels::{LevelProgress, find_level, level_entities},
+};
 
-use super::Player;
+/// Marks the spawn points of a level, players get placed from the project data
+#[derive(Default, Component)]
+pub struct SpawnPoint;
 
-#[derive(Default, Bundle)]
+#[derive(Default, Bundle, LdtkEntity)]
 pub struct SpawnPointBundle {
-    locations: SpawnLocation,
+    spawn_point: SpawnPoint,
 }
-#[derive(Default, Component, Clone)]
-pub struct SpawnLocation(Vec2);
 
-#[derive(Default, Clone)]
-pub struct PlayerSpawnState {
-    pub loaded_spawns: Vec<Vec2>,
-    original_spawns: Vec<Vec2>,
-    pub players_waiting: Vec<Entity>,
+/// A spawn point in world coordinates, reserved for one player by its `player_handle` field
+#[derive(Clone, Copy, Debug)]
+pub struct Spawn {
+    pub position: Vec2,
+    pub handle: Option<usize>,
 }
 
-impl PlayerSpawnState {
-    pub fn get_spawn(&mut self) -> Vec2 {
-        let result = match self.loaded_spawns.pop() {
-            None => {
-                self.loaded_spawns = self.original_spawns.clone();
-                None
-            }
-            Some(spawn) => Some(spawn),
-        };
-        if result.is_none() && self.original_spawns.len() == 0 {
-            panic!("Trying to get spawns that aren't loaded");
-        }
-        result.expect("")
-    }
+/// Spawn points of a level, in the order they are saved in, which is the same for every peer
+pub fn level_spawns(level: &Level) -> Vec<Spawn> {
+    level_entities(level, SPAWN_POINT_ENTITY)
+        .map(|(entity, rect)| Spawn {
+            position: rect.center(),
+            handle: entity
+                .get_maybe_int_field("player_handle")
+                .ok()
+                .copied()
+                .flatten()
+                .and_then(|handle| usize::try_from(handle).ok()),
+        })
+        .collect()
 }
 
-#[derive(Event)]
-pub struct SpawnPlayerEvent(pub Entity);
-
-impl LdtkEntity for SpawnPointBundle {
-    fn bundle_entity(
-        entity_instance: &EntityInstance,
-        layer_instance: &LayerInstance,
-        _: Option<&Handle<Image>>,
-        _: Option<&TilesetDefinition>,
-        _: &AssetServer,
-        _: &mut Assets<TextureAtlasLayout>,
-    ) -> SpawnPointBundle {
-        let spawn = ldtk_pixel_coords_to_translation_pivoted(
-            entity_instance.px,
-            layer_instance.c_hei * layer_instance.grid_size,
-            IVec2::new(entity_instance.width, entity_instance.height),
-            entity_instance.pivot,
-        );
+/// Where a player spawns: the spawn point reserved for its handle, otherwise the
+/// spawn points nobody reserved taken in turns by handle, and when there are
+/// none of those any spawn point.
+///
+/// Only `None` without any spawn points.
+pub fn spawn_for_handle(spawns: &[Spawn], handle: usize) -> Option<Vec2> {
+    if let Some(spawn) = spawns.iter().find(|spawn| spawn.handle == Some(handle)) {
+        return Some(spawn.position);
+    }
+    let shared: Vec<&Spawn> = spawns
+        .iter()
+        .filter(|spawn| spawn.handle.is_none())
+        .collect();
+    let candidates = if shared.is_empty() {
+        spawns.iter().collect()
+    } else {
+        shared
+    };
+    candidates
+        .get(handle % candidates.len().max(1))
+        .map(|spawn| spawn.position)
+}
 
-        let locations = SpawnLocation(spawn);
+/// Why players can't be spawned
+#[derive(Clone, Debug)]
+pub enum SpawnError {
+    /// The level to spawn into isn't in the project
+    MissingLevel(String),
+    /// The level has no `SpawnPoint`
+    NoSpawnPoints(String),
+}
 
-        SpawnPointBundle { locations }
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::MissingLevel(iid) => write!(f, "Level {} is not in the project", iid),
+            SpawnError::NoSpawnPoints(level) => write!(f, "{} has no spawn points", level),
+        }
     }
 }
-pub fn spawn_player(
-    mut spawn_points_q: Query<(Entity, &mut SpawnLocation)>,
-    mut players_q: Query<&mut Transform, With<Player>>,
-    mut player_entered: EventReader<SpawnPlayerEvent>,
-    level_query: Query<(Entity, &LevelIid)>,
-    level_selection: Res<LevelSelection>,
-    mut local: Local<PlayerSpawnState>,
-    mut commands: Commands,
-) {
-    for (entity, spawn_location) in &mut spawn_points_q {
-        local.loaded_spawns.push(spawn_location.0.clone());
-        commands.entity(entity).despawn();
-    }
 
-    if local.loaded_spawns.len() == 0 {
-        return;
-    }
+/// Whether the players can be spawned into the current level
+#[derive(Resource, Default, Clone)]
+pub struct PlayerSpawnState {
+    /// Set while the level has nowhere to put the players, cleared once the project is fixed
+    pub error: Option<SpawnError>,
+}
 
-    for player_event in &mut player_entered.read() {
-        local.players_waiting.push(player_event.0);
+impl PlayerSpawnState {
+    /// Forgets the problems of the last match
+    pub fn reset(&mut self) {
+        self.error = None;
     }
+}
 
-    let current_level_iid = match &*level_selection {
-        LevelSelection::Iid(iid) => iid,
-        _ => panic!("Please use iid only thx uwu"),
+/// Checks that the current level has spawn points for `respawn_dead_players` to use.
+///
+/// Spawning itself happens in the rollback simulation, new players start out
+/// `Dead` and come back on a fixed frame.
+pub fn check_spawn_points(
+    progress: Res<LevelProgress>,
+    mut spawn_state: ResMut<PlayerSpawnState>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let Some(project) = ldtk_project_assets.get(ldtk_projects.single()) else {
+        return;
     };
-
-    let mut parent_entity_option: Option<Entity> = None;
-
-    for (entity, level_id) in &level_query {
-        if *level_id == *current_level_iid {
-            parent_entity_option = Some(entity.clone());
-            break;
+    let error = match find_level(project, progress.level_iid()) {
+        None => Some(SpawnError::MissingLevel(progress.level_iid().to_string())),
+        Some(level) if level_spawns(level).is_empty() => {
+            Some(SpawnError::NoSpawnPoints(level.identifier.clone()))
         }
+        Some(_) => None,
+    };
+    if let (Some(error), None) = (&error, &spawn_state.error) {
+        error!("Can't spawn players: {}", error);
     }
+    spawn_state.error = error;
+}
 
-    let current_level_entity = parent_entity_option.expect("Current level not found");
-
-    while let Some(player) = local.players_waiting.pop() {
-        if local.loaded_spawns.len() == 0 {
-            local.players_waiting.push(player);
-            return;
-        }
-        info!("Spawning player {}", player);
-        let mut player_transform = players_q
-            .get_mut(player)
-            .expect("Event sent about nonexisting player");
-        let spawn = local.get_spawn();
-        player_transform.translation = Vec3::from_array([spawn.x, spawn.y, PLAYER_Z]);
-        player_transform.scale = Vec3::from((0.5, 0.5, 0.5));
-        commands.entity(player).set_parent(current_level_entity);
-    }
+pub fn show_spawn_error(mut contexts: EguiContexts, spawn_state: Res<PlayerSpawnState>) {
+    let Some(error) = &spawn_state.error else {
+        return;
+    };
+    egui::Window::new("Can't start the match")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(error.to_string());
+            ui.label("Fix the level and the players spawn once it reloads.");
+        });
 }
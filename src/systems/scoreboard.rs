@@ -0,0 +1,163 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{EguiContexts, egui};
+
+use crate::game::GameState;
+
+use super::{
+    bots::Bot,
+    death::Dead,
+    interactibles::{InteractibleState, InteractibleStates},
+    multiplayer::{Local, identity::PlayerTag, messages::PeerPings},
+    pixel_perfect::{GameCamera, WindowCamera, world_to_window},
+    player::{Player, camera::camera_follow_local_players},
+};
+
+/// How far above the center of a player its name tag floats, in world pixels
+const NAME_TAG_HEIGHT: f32 = 10.;
+/// Round trips up to these many milliseconds are shown as good and as okay, slower as bad
+const GOOD_PING_MS: f32 = 80.;
+const OKAY_PING_MS: f32 = 160.;
+
+fn ping_color(rtt: f32) -> egui::Color32 {
+    if rtt <= GOOD_PING_MS {
+        egui::Color32::from_rgb(110, 200, 90)
+    } else if rtt <= OKAY_PING_MS {
+        egui::Color32::from_rgb(240, 170, 50)
+    } else {
+        egui::Color32::from_rgb(230, 80, 70)
+    }
+}
+
+/// Pickups a player collected in the current level
+fn pickups_of(states: &InteractibleStates, handle: usize) -> usize {
+    states
+        .0
+        .values()
+        .filter(|state| {
+            matches!(state, InteractibleState::Pickup { collected_by } if *collected_by == handle)
+        })
+        .count()
+}
+
+/// Floats the name of every player above it, with a dot for the ping of the peer
+/// controlling it. In split screen a player gets a tag in every view it is in.
+#[allow(clippy::type_complexity)]
+pub fn draw_name_tags(
+    mut contexts: EguiContexts,
+    players: Query<(Entity, &PlayerTag, &Transform), Without<Dead>>,
+    cameras: Query<(Entity, &Camera, &Transform), With<GameCamera>>,
+    window_cameras: Query<&OrthographicProjection, With<WindowCamera>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    pings: Res<PeerPings>,
+) {
+    let (Ok(window), Ok(window_projection)) = (windows.get_single(), window_cameras.get_single())
+    else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+
+    for (camera_entity, camera, camera_transform) in &cameras {
+        // Nothing here has a parent, and the global transforms are only updated after Update
+        let camera_transform = GlobalTransform::from(*camera_transform);
+        for (player, tag, transform) in &players {
+            let above = transform.translation + Vec3::Y * NAME_TAG_HEIGHT;
+            let Some(position) =
+                world_to_window(camera, &camera_transform, window, window_projection, above)
+            else {
+                continue;
+            };
+            let ping = tag.peer.and_then(|peer| pings.0.get(&peer).copied());
+
+            egui::Area::new(egui::Id::new(("name tag", player, camera_entity)))
+                .fixed_pos(egui::pos2(position.x, position.y))
+                .pivot(egui::Align2::CENTER_BOTTOM)
+                .order(egui::Order::Background)
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::none()
+                        .fill(egui::Color32::from_black_alpha(160))
+                        .rounding(3.)
+                        .inner_margin(egui::Margin::symmetric(4., 1.))
+                        .show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(
+                                    egui::RichText::new(&tag.identity.name)
+                                        .color(tag.identity.egui_color())
+                                        .strong(),
+                                );
+                                if let Some(rtt) = ping {
+                                    ui.label(egui::RichText::new("●").color(ping_color(rtt)));
+                                }
+                            });
+                        });
+                });
+        }
+    }
+}
+
+/// Lists the players while Tab is held
+#[allow(clippy::type_complexity)]
+pub fn scoreboard_window(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    players: Query<(&Player, &PlayerTag, Has<Local>, Has<Bot>)>,
+    states: Res<InteractibleStates>,
+    pings: Res<PeerPings>,
+) {
+    if !keys.pressed(KeyCode::Tab) || players.is_empty() {
+        return;
+    }
+    let mut players: Vec<_> = players.iter().collect();
+    players.sort_by_key(|(player, ..)| player.handle);
+
+    egui::Window::new("Scoreboard")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0., 16.])
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("scoreboard")
+                .striped(true)
+                .min_col_width(48.)
+                .show(ui, |ui| {
+                    ui.strong("Player");
+                    ui.strong("Pickups");
+                    ui.strong("Ping");
+                    ui.end_row();
+
+                    for (player, tag, is_local, is_bot) in players {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(tag.identity.egui_color(), &tag.identity.name);
+                            if is_bot {
+                                ui.weak("bot");
+                            } else if is_local {
+                                ui.weak("you");
+                            }
+                        });
+                        ui.label(pickups_of(&states, player.handle).to_string());
+                        match tag.peer.and_then(|peer| pings.0.get(&peer).copied()) {
+                            Some(rtt) => {
+                                ui.colored_label(ping_color(rtt), format!("{:.0} ms", rtt))
+                            }
+                            None => ui.label("-"),
+                        };
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+/// Name tags above the players and the scoreboard
+pub struct ScoreboardPlugin;
+
+impl Plugin for ScoreboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                draw_name_tags.after(camera_follow_local_players),
+                scoreboard_window,
+            )
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
@@ -1,41 +1,209 @@
-use bevy::prelude::*;
+use std::path::Path;
+
+use bevy::{
+    asset::{LoadState, RecursiveDependencyLoadState},
+    prelude::*,
+};
 use bevy_ecs_ldtk::prelude::*;
+use bevy_egui::{EguiContexts, egui};
 
 use crate::game::GameState;
 
+use super::parallax::BACKGROUND_VARIANTS;
+
+const WORLD_PATH: &str = "world.ldtk";
+/// Our players look like this, everybody else's like the other one
+const LOCAL_PLAYER_TEXTURE: &str = "atlas/kornel.png";
+const REMOTE_PLAYER_TEXTURE: &str = "atlas/wera.png";
+
+/// Everything loaded before the menu shows up, held on to so nothing gets
+/// loaded again in the middle of a match
 #[derive(Resource)]
-pub struct LdtkLoading {
-    ldtk_handle: LdtkProjectHandle,
+pub struct GameAssets {
+    pub local_player: Handle<Image>,
+    pub remote_player: Handle<Image>,
+    /// Every preloaded asset by path, the project among them
+    preloaded: Vec<(&'static str, UntypedHandle)>,
+    ldtk_handle: Handle<LdtkProject>,
+}
+
+/// Share of `GameAssets` loaded so far, from 0 to 1
+#[derive(Resource, Default)]
+pub struct LoadingProgress(pub f32);
+
+/// An asset that couldn't be loaded
+#[derive(Resource, Debug)]
+pub struct LoadingFailure {
+    pub path: String,
+    pub reason: String,
+}
+
+/// The tileset the project failed on. The project loads its tilesets itself,
+/// so a missing one shows up as the project failing.
+fn failed_tileset(project: &LdtkProject, asset_server: &AssetServer) -> Option<LoadingFailure> {
+    // Tileset paths are relative to the project file
+    let dir = Path::new(WORLD_PATH).parent().unwrap_or(Path::new(""));
+    project
+        .json_data()
+        .defs
+        .tilesets
+        .iter()
+        .filter_map(|tileset| tileset.rel_path.as_ref())
+        .find_map(|rel_path| {
+            let path = dir.join(rel_path);
+            let id = asset_server.get_path_id(path.clone())?;
+            match asset_server.get_load_state(id) {
+                Some(LoadState::Failed(err)) => Some(LoadingFailure {
+                    path: path.to_string_lossy().into_owned(),
+                    reason: err.to_string(),
+                }),
+                _ => None,
+            }
+        })
 }
 
 pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn((
-        Camera2d,
-        OrthographicProjection {
-            scale: 0.25,
-            ..OrthographicProjection::default_2d()
-        },
-    ));
-
-    info!("Loading world");
-    let ldtk_handle: LdtkProjectHandle = asset_server.load("world.ldtk").into();
-    commands.insert_resource(LdtkLoading {
+    info!("Loading assets");
+    let mut preloaded = Vec::new();
+    let mut preload = |path: &'static str| -> Handle<Image> {
+        let handle = asset_server.load(path);
+        preloaded.push((path, handle.clone().untyped()));
+        handle
+    };
+    let local_player = preload(LOCAL_PLAYER_TEXTURE);
+    let remote_player = preload(REMOTE_PLAYER_TEXTURE);
+    for (path, _) in BACKGROUND_VARIANTS {
+        preload(path);
+    }
+
+    let ldtk_handle: Handle<LdtkProject> = asset_server.load(WORLD_PATH);
+    preloaded.push((WORLD_PATH, ldtk_handle.clone().untyped()));
+    commands.insert_resource(GameAssets {
+        local_player,
+        remote_player,
+        preloaded,
         ldtk_handle: ldtk_handle.clone(),
     });
+
     info!("Constructing world");
     commands.spawn(LdtkWorldBundle {
-        ldtk_handle,
+        ldtk_handle: ldtk_handle.into(),
         ..Default::default()
     });
     info!("World spawned")
 }
 
+/// Moves on to the menu once everything is loaded, or to the error screen while
+/// anything failed to load
 pub fn check_asset_loading(
-    mut next_state: ResMut<NextState<GameState>>,
-    loading_status: Res<LdtkLoading>,
+    mut commands: Commands,
+    assets: Res<GameAssets>,
     asset_server: Res<AssetServer>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+    mut progress: ResMut<LoadingProgress>,
+    game_state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if asset_server.is_loaded_with_dependencies(loading_status.ldtk_handle.clone()) {
-        next_state.set(GameState::Playing);
+    let mut loaded = 0;
+    let mut failure = None;
+    for (path, handle) in &assets.preloaded {
+        match asset_server.get_recursive_dependency_load_state(handle.id()) {
+            Some(RecursiveDependencyLoadState::Loaded) => loaded += 1,
+            Some(RecursiveDependencyLoadState::Failed(err)) if failure.is_none() => {
+                failure = Some(LoadingFailure {
+                    path: path.to_string(),
+                    reason: err.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+    // Blame the tileset rather than the project
+    if failure
+        .as_ref()
+        .is_some_and(|failure| failure.path == WORLD_PATH)
+    {
+        if let Some(tileset) = ldtk_project_assets
+            .get(&assets.ldtk_handle)
+            .and_then(|project| failed_tileset(project, &asset_server))
+        {
+            failure = Some(tileset);
+        }
     }
+    progress.0 = loaded as f32 / assets.preloaded.len() as f32;
+
+    let next = match failure {
+        Some(failure) => {
+            if *game_state.get() != GameState::LoadingFailed {
+                error!("Failed to load {}: {}", failure.path, failure.reason);
+            }
+            commands.insert_resource(failure);
+            GameState::LoadingFailed
+        }
+        None => {
+            commands.remove_resource::<LoadingFailure>();
+            if loaded == assets.preloaded.len() {
+                GameState::MainMenu
+            } else {
+                // Retried assets are loading again
+                GameState::Loading
+            }
+        }
+    };
+    if *game_state.get() != next {
+        next_state.set(next);
+    }
+}
+
+pub fn loading_screen(mut contexts: EguiContexts, progress: Res<LoadingProgress>) {
+    egui::Window::new("Loading")
+        .collapsible(false)
+        .resizable(false)
+        .title_bar(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label("Loading...");
+            ui.add(
+                egui::ProgressBar::new(progress.0)
+                    .desired_width(200.)
+                    .show_percentage(),
+            );
+        });
+}
+
+/// Names the asset that failed and loads everything that isn't loaded again on retry
+pub fn loading_failed_screen(
+    mut contexts: EguiContexts,
+    failure: Res<LoadingFailure>,
+    assets: Res<GameAssets>,
+    asset_server: Res<AssetServer>,
+) {
+    egui::Window::new("Loading failed")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(format!("Couldn't load {}", failure.path));
+            ui.label(egui::RichText::new(&failure.reason).weak());
+            if ui.button("Retry").clicked() {
+                info!("Retrying to load assets");
+                for (path, handle) in &assets.preloaded {
+                    let loaded = matches!(
+                        asset_server.get_recursive_dependency_load_state(handle.id()),
+                        Some(RecursiveDependencyLoadState::Loaded)
+                    );
+                    if !loaded {
+                        asset_server.reload(*path);
+                    }
+                }
+                // A tileset isn't preloaded itself, the project only loads it once
+                if !assets
+                    .preloaded
+                    .iter()
+                    .any(|(path, _)| *path == failure.path)
+                {
+                    asset_server.reload(failure.path.clone());
+                }
+            }
+        });
 }
@@ -0,0 +1,230 @@
+use avian2d::prelude::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_ecs_ldtk::{ldtk::WorldLayout, prelude::*};
+use bevy_ggrs::{PlayerInputs, Rollback, RollbackFrameCount};
+
+use crate::config::MultiplayerConfig;
+
+use super::{
+    death::Dead,
+    levels::{LevelProgress, all_levels_ready, find_level, level_bounds},
+    multiplayer::{READY_LAG, handshake::RollbackRegistryExt},
+    player::Player,
+};
+
+/// A level streamed in around the players
+#[derive(Clone, Debug)]
+pub struct StreamedLevel {
+    /// Frame the simulation asked for it on
+    pub requested_at: i32,
+    /// Whether players may walk into it, once every peer has it loaded
+    pub open: bool,
+    /// Frame since which no player is near it anymore
+    pub unwanted_since: Option<i32>,
+}
+
+/// Levels streamed in around the players, by iid.
+///
+/// Rolled back with the rest of the simulation, so every peer lets players into
+/// a level on the same frame, however long it took to load there.
+#[derive(Resource, Clone, Default, Debug)]
+pub struct StreamedLevels(pub HashMap<String, StreamedLevel>);
+
+/// Whether the project is one connected world streamed in around the players,
+/// rather than levels played one after another.
+///
+/// Every peer loads the same file, so they all agree on it.
+pub fn is_streamed(project: &LdtkProject) -> bool {
+    matches!(
+        project.json_data().world_layout,
+        Some(WorldLayout::GridVania | WorldLayout::Free)
+    )
+}
+
+/// Levels the players are in, the level the simulation spawns them into
+/// and the neighbours of all of them
+pub fn streamed_levels(
+    project: &LdtkProject,
+    progress: &LevelProgress,
+    positions: impl Iterator<Item = Vec2>,
+) -> HashSet<String> {
+    let levels = &project.json_data().levels;
+    let positions: Vec<Vec2> = positions.collect();
+
+    let occupied = levels
+        .iter()
+        .filter(|level| {
+            let bounds = level_bounds(level);
+            positions.iter().any(|position| bounds.contains(*position))
+        })
+        .chain(find_level(project, progress.level_iid()));
+
+    occupied
+        .flat_map(|level| {
+            std::iter::once(level.iid.clone()).chain(
+                level
+                    .neighbours
+                    .iter()
+                    .map(|neighbour| neighbour.level_iid.clone()),
+            )
+        })
+        .collect()
+}
+
+/// Keeps `StreamedLevels` around the living players.
+///
+/// New levels open once every peer reports in its inputs that it has them loaded.
+/// Only inputs sent `READY_LAG` frames after a level came up count, earlier ones
+/// could come from a simulation that didn't ask for it yet. For the same reason
+/// levels nobody is near anymore are only dropped `READY_LAG` frames later.
+pub fn track_streamed_levels(
+    mut streamed: ResMut<StreamedLevels>,
+    progress: Res<LevelProgress>,
+    players: Query<&Position, (With<Player>, With<Rollback>, Without<Dead>)>,
+    frame: Res<RollbackFrameCount>,
+    inputs: Res<PlayerInputs<MultiplayerConfig>>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let Some(project) = ldtk_project_assets.get(ldtk_projects.single()) else {
+        return;
+    };
+    if !is_streamed(project) {
+        return;
+    }
+    let frame: i32 = (*frame).into();
+
+    let wanted = streamed_levels(
+        project,
+        &progress,
+        players.iter().map(|position| position.0),
+    );
+    for (iid, level) in &mut streamed.0 {
+        if wanted.contains(iid) {
+            level.unwanted_since = None;
+        } else {
+            level.unwanted_since.get_or_insert(frame);
+        }
+    }
+    streamed.0.retain(|_, level| {
+        level
+            .unwanted_since
+            .is_none_or(|since| frame - since < READY_LAG)
+    });
+    for iid in wanted {
+        streamed.0.entry(iid).or_insert(StreamedLevel {
+            requested_at: frame,
+            open: false,
+            unwanted_since: None,
+        });
+    }
+
+    if all_levels_ready(&inputs) {
+        for level in streamed.0.values_mut() {
+            if !level.open && frame - level.requested_at >= READY_LAG {
+                level.open = true;
+            }
+        }
+    }
+}
+
+/// Holds players back at the edge of levels that aren't open yet
+pub fn confine_to_streamed_levels(
+    streamed: Res<StreamedLevels>,
+    mut players: Query<
+        (&mut Position, &mut LinearVelocity),
+        (With<Player>, With<Rollback>, Without<Dead>),
+    >,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    let Some(project) = ldtk_project_assets.get(ldtk_projects.single()) else {
+        return;
+    };
+    if !is_streamed(project) {
+        return;
+    }
+
+    let (open, closed): (Vec<_>, Vec<_>) = project
+        .json_data()
+        .levels
+        .iter()
+        .partition(|level| streamed.0.get(&level.iid).is_some_and(|level| level.open));
+    let open: Vec<Rect> = open.into_iter().map(level_bounds).collect();
+    let closed: Vec<Rect> = closed.into_iter().map(level_bounds).collect();
+
+    for (mut position, mut velocity) in &mut players {
+        let inside = |bounds: &Rect| bounds.contains(position.0);
+        if open.iter().any(inside) || !closed.iter().any(inside) {
+            continue;
+        }
+        // Back onto the closest open level
+        let Some(held) = open
+            .iter()
+            .map(|bounds| position.0.clamp(bounds.min, bounds.max))
+            .min_by(|a, b| {
+                a.distance_squared(position.0)
+                    .total_cmp(&b.distance_squared(position.0))
+            })
+        else {
+            continue;
+        };
+        if held.x != position.x {
+            velocity.x = 0.;
+        }
+        if held.y != position.y {
+            velocity.y = 0.;
+        }
+        position.0 = held;
+    }
+}
+
+/// Loads the levels in `StreamedLevels` and the current level before there are
+/// any players, and unloads the rest, instead of following `LevelSelection`
+pub fn stream_levels(
+    mut commands: Commands,
+    streamed: Res<StreamedLevels>,
+    progress: Res<LevelProgress>,
+    level_selection: Option<Res<LevelSelection>>,
+    mut worlds: Query<(&LdtkProjectHandle, &mut LevelSet)>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    for (handle, mut level_set) in &mut worlds {
+        let Some(project) = ldtk_project_assets.get(handle) else {
+            continue;
+        };
+        if !is_streamed(project) {
+            continue;
+        }
+        // `LevelSelection` would overwrite the level set every frame
+        if level_selection.is_some() {
+            info!("Streaming levels around the players");
+            commands.remove_resource::<LevelSelection>();
+        }
+
+        let mut iids: HashSet<String> = streamed.0.keys().cloned().collect();
+        iids.insert(progress.level_iid().to_string());
+        if level_set.iids.len() != iids.len()
+            || !iids
+                .iter()
+                .all(|iid| level_set.iids.contains(&LevelIid::new(iid)))
+        {
+            debug!("Streaming in {} levels", iids.len());
+            *level_set = LevelSet::from_iids(iids);
+        }
+    }
+}
+
+/// Streams in the levels around the players in GridVania and free LDtk worlds
+pub struct StreamingPlugin;
+
+impl Plugin for StreamingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StreamedLevels>()
+            .register_rollback_resource::<StreamedLevels>()
+            .add_systems(Update, stream_levels);
+    }
+}
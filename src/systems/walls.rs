@@ -1,18 +1,350 @@
 use avian2d::prelude::*;
+use bevy::render::{
+    mesh::Indices, render_asset::RenderAssetUsages, render_resource::PrimitiveTopology,
+};
 use bevy::{
     prelude::*,
     utils::{HashSet, hashbrown::HashMap},
 };
 use bevy_ecs_ldtk::prelude::*;
 
+use crate::config::{HAZARD_CELL, ONE_WAY_CELL};
+pub use crate::config::{SLOPE_TILES, Surface, WALL_MATERIALS, WallMaterial};
+
+use super::{controller::collision_masks::LayerEnum, platforms::OneWayPlatform};
+
+const HAZARD_COLOR: Color = Color::srgba(0.85, 0.15, 0.1, 0.7);
+const PLATFORM_COLOR: Color = Color::srgb(0.72, 0.44, 0.31);
+const SLOPE_COLOR: Color = Color::srgb(0.56, 0.34, 0.23);
+
+/// Angle of the steepest slope tiles in radians
+pub fn steepest_slope_angle() -> f32 {
+    SLOPE_TILES
+        .iter()
+        .map(|(_, left, right)| ((left - right).abs() as f32 / 2.).atan())
+        .fold(0., f32::max)
+}
+
+/// A wall tile, holding its IntGrid value to look up its `WallMaterial`
 #[derive(Default, Component)]
-pub struct Wall;
+pub struct Wall(pub i32);
+
+impl From<IntGridCell> for Wall {
+    fn from(cell: IntGridCell) -> Self {
+        Wall(cell.value)
+    }
+}
 
 #[derive(Default, Bundle, LdtkIntCell)]
 pub struct WallBundle {
+    #[from_int_grid_cell]
     wall: Wall,
 }
 
+/// Surface of a merged wall collider
+#[derive(Component, Clone, Copy, Default)]
+pub struct WallSurface(pub Surface);
+
+/// Spikes, water and anything else that kills on contact
+#[derive(Default, Component)]
+pub struct Hazard;
+
+#[derive(Default, Bundle, LdtkIntCell)]
+pub struct HazardBundle {
+    hazard: Hazard,
+}
+
+/// A ramp tile, holding its IntGrid value to look up its shape in `SLOPE_TILES`
+#[derive(Default, Component)]
+pub struct Slope(pub i32);
+
+impl From<IntGridCell> for Slope {
+    fn from(cell: IntGridCell) -> Self {
+        Slope(cell.value)
+    }
+}
+
+#[derive(Default, Bundle, LdtkIntCell)]
+pub struct SlopeBundle {
+    #[from_int_grid_cell]
+    slope: Slope,
+}
+
+/// Tiles players can jump onto from below
+#[derive(Default, Component)]
+pub struct OneWay;
+
+#[derive(Default, Bundle, LdtkIntCell)]
+pub struct OneWayBundle {
+    one_way: OneWay,
+}
+
+/// Sensor covering a group of merged hazard tiles
+#[derive(Component)]
+pub struct HazardZone;
+
+/// World space rectangles of the merged wall colliders, per level entity
+#[derive(Resource, Default)]
+pub struct WallGeometry(pub HashMap<Entity, Vec<bevy::math::Rect>>);
+
+/// Represents a wide wall that is 1 tile tall
+/// Used to spawn wall collisions
+#[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct Plate {
+    left: i32,
+    right: i32,
+}
+
+/// A simple rectangle type representing a wall of any size
+struct TileRect {
+    left: i32,
+    right: i32,
+    top: i32,
+    bottom: i32,
+}
+
+impl TileRect {
+    fn size(&self, grid_size: i32) -> Vec2 {
+        IVec2::new(self.right - self.left + 1, self.top - self.bottom + 1).as_vec2()
+            * grid_size as f32
+    }
+
+    /// Center relative to the level
+    fn center(&self, grid_size: i32) -> Vec2 {
+        IVec2::new(self.left + self.right + 1, self.bottom + self.top + 1).as_vec2()
+            * grid_size as f32
+            / 2.
+    }
+}
+
+/// Surface of consecutive slope tiles with the same gradient,
+/// in half tiles relative to the level
+#[derive(Debug, Clone, Copy)]
+struct SlopeRun {
+    start: IVec2,
+    end: IVec2,
+}
+
+impl SlopeRun {
+    fn continues_with(&self, next: &SlopeRun) -> bool {
+        let rise = self.end.y - self.start.y;
+        let run = self.end.x - self.start.x;
+        let next_rise = next.end.y - next.start.y;
+        let next_run = next.end.x - next.start.x;
+        self.end == next.start && rise * next_run == next_rise * run
+    }
+
+    /// Outline of the ramp down to the bottom of its lowest tile,
+    /// counter clockwise and relative to the level
+    fn outline(&self, grid_size: i32) -> Vec<Vec2> {
+        let base = self.start.y.min(self.end.y) / 2 * 2;
+        let mut points = vec![IVec2::new(self.start.x, base), IVec2::new(self.end.x, base)];
+        for top in [self.end, self.start] {
+            if !points.contains(&top) {
+                points.push(top);
+            }
+        }
+        points
+            .into_iter()
+            .map(|point| point.as_vec2() * grid_size as f32 / 2.)
+            .collect()
+    }
+}
+
+/// Chains slope tiles with the same gradient into runs, each a single convex piece
+fn merge_slopes(slopes: &[(GridCoords, i32)]) -> Vec<SlopeRun> {
+    let mut segments: Vec<SlopeRun> = slopes
+        .iter()
+        .filter_map(|(coords, value)| {
+            let (_, left, right) = SLOPE_TILES.iter().find(|(v, ..)| v == value)?;
+            let corner = IVec2::new(coords.x, coords.y) * 2;
+            Some(SlopeRun {
+                start: corner + IVec2::new(0, *left),
+                end: corner + IVec2::new(2, *right),
+            })
+        })
+        .collect();
+    // Left to right, so runs grow one tile at a time
+    segments.sort_by_key(|segment| (segment.start.x, segment.start.y));
+
+    let mut runs: Vec<SlopeRun> = Vec::new();
+    for segment in segments {
+        match runs.iter_mut().find(|run| run.continues_with(&segment)) {
+            Some(run) => run.end = segment.end,
+            None => runs.push(segment),
+        }
+    }
+    runs
+}
+
+/// Groups ramps that meet end to end, whatever their gradient, from left to right
+fn chain_slopes(mut runs: Vec<SlopeRun>) -> Vec<Vec<SlopeRun>> {
+    runs.sort_by_key(|run| (run.start.x, run.start.y));
+
+    let mut chains: Vec<Vec<SlopeRun>> = Vec::new();
+    for run in runs {
+        let continued = chains
+            .iter_mut()
+            .find(|chain| chain.last().is_some_and(|last| last.end == run.start));
+        match continued {
+            Some(chain) => chain.push(run),
+            None => chains.push(vec![run]),
+        }
+    }
+    chains
+}
+
+/// Top of a chain of ramps from left to right in half tiles, continued for a tile
+/// onto the floor it starts or ends on. `is_floor` tells whether a tile is a wall
+/// with nothing on top.
+fn chain_surface(chain: &[SlopeRun], is_floor: impl Fn(IVec2) -> bool) -> Vec<IVec2> {
+    let first = chain[0].start;
+    let last = chain[chain.len() - 1].end;
+
+    let mut points = Vec::new();
+    if first.y % 2 == 0 && is_floor(IVec2::new(first.x / 2 - 1, first.y / 2 - 1)) {
+        points.push(first - IVec2::new(2, 0));
+    }
+    points.push(first);
+    points.extend(chain.iter().map(|run| run.end));
+    if last.y % 2 == 0 && is_floor(IVec2::new(last.x / 2, last.y / 2 - 1)) {
+        points.push(last + IVec2::new(2, 0));
+    }
+    points
+}
+
+/// Flat mesh filling convex outlines
+fn outline_mesh(outlines: &[Vec<Vec2>]) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    for outline in outlines {
+        let first = positions.len() as u32;
+        positions.extend(outline.iter().map(|point| [point.x, point.y, 0.]));
+        indices
+            .extend((1..outline.len() as u32 - 1).flat_map(|i| [first, first + i, first + i + 1]));
+    }
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+/// Groups int grid tiles by the level they belong to
+fn tiles_by_level<'a>(
+    tiles: impl Iterator<Item = (&'a GridCoords, &'a Parent)>,
+    parent_query: &Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+) -> HashMap<Entity, HashSet<GridCoords>> {
+    let mut level_to_tiles: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
+
+    tiles.for_each(|(&grid_coords, parent)| {
+        // An intgrid tile's direct parent will be a layer entity, not the level entity
+        // To get the level entity, you need the tile's grandparent.
+        // This is where parent_query comes in.
+        if let Ok(grandparent) = parent_query.get(parent.get()) {
+            level_to_tiles
+                .entry(grandparent.get())
+                .or_default()
+                .insert(grid_coords);
+        }
+    });
+
+    level_to_tiles
+}
+
+/// Grid width, height and cell size of a spawned level
+fn level_grid(
+    level_iid: &LevelIid,
+    ldtk_projects: &Query<&LdtkProjectHandle>,
+    ldtk_project_assets: &Assets<LdtkProject>,
+) -> (i32, i32, i32) {
+    let ldtk_project = ldtk_project_assets
+        .get(ldtk_projects.single())
+        .expect("Project should be loaded if level has spawned");
+
+    let level = ldtk_project
+        .as_standalone()
+        .get_loaded_level_by_iid(&level_iid.to_string())
+        .expect("Spawned level should exist in LDtk project");
+
+    let LayerInstance {
+        c_wid: width,
+        c_hei: height,
+        grid_size,
+        ..
+    } = level.layer_instances()[0];
+
+    (width, height, grid_size)
+}
+
+/// Merges tiles into as few rectangles as reasonably possible
+///
+/// The algorithm used here is a nice compromise between simplicity, speed,
+/// and a small number of rectangles.
+/// In basic terms, it will:
+/// 1. combine tiles into flat "plates" in each individual row
+/// 2. combine the plates into rectangles across multiple rows wherever possible
+fn merge_tiles(tiles: &HashSet<GridCoords>, width: i32, height: i32) -> Vec<TileRect> {
+    // combine tiles into flat "plates" in each individual row
+    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
+
+    for y in 0..height {
+        let mut row_plates: Vec<Plate> = Vec::new();
+        let mut plate_start = None;
+
+        // + 1 to the width so the algorithm "terminates" plates that touch the right edge
+        for x in 0..width + 1 {
+            match (plate_start, tiles.contains(&GridCoords { x, y })) {
+                (Some(s), false) => {
+                    row_plates.push(Plate {
+                        left: s,
+                        right: x - 1,
+                    });
+                    plate_start = None;
+                }
+                (None, true) => plate_start = Some(x),
+                _ => (),
+            }
+        }
+
+        plate_stack.push(row_plates);
+    }
+
+    // combine "plates" into rectangles across multiple rows
+    let mut rect_builder: HashMap<Plate, TileRect> = HashMap::new();
+    let mut prev_row: Vec<Plate> = Vec::new();
+    let mut rects: Vec<TileRect> = Vec::new();
+
+    // an extra empty row so the algorithm "finishes" the rects that touch the top edge
+    plate_stack.push(Vec::new());
+
+    for (y, current_row) in plate_stack.into_iter().enumerate() {
+        for prev_plate in &prev_row {
+            if !current_row.contains(prev_plate) {
+                // remove the finished rect so that the same plate in the future starts a new rect
+                if let Some(rect) = rect_builder.remove(prev_plate) {
+                    rects.push(rect);
+                }
+            }
+        }
+        for plate in &current_row {
+            rect_builder
+                .entry(plate.clone())
+                .and_modify(|e| e.top += 1)
+                .or_insert(TileRect {
+                    bottom: y as i32,
+                    top: y as i32,
+                    left: plate.left,
+                    right: plate.right,
+                });
+        }
+        prev_row = current_row;
+    }
+
+    rects
+}
+
 /// Spawns heron collisions for the walls of a level
 ///
 /// You could just insert a ColliderBundle into the WallBundle,
@@ -22,37 +354,61 @@ pub struct WallBundle {
 /// Instead, by flagging the wall tiles and spawning the collisions later,
 /// we can minimize the amount of colliding entities.
 ///
-/// The algorithm used here is a nice compromise between simplicity, speed,
-/// and a small number of rectangle colliders.
 /// In basic terms, it will:
-/// 1. consider where the walls are
-/// 2. combine wall tiles into flat "plates" in each individual row
-/// 3. combine the plates into rectangles across multiple rows wherever possible
-/// 4. spawn colliders for each rectangle
+/// 1. consider where the walls of each material are
+/// 2. merge them into rectangles with `merge_tiles`
+/// 3. spawn colliders for each rectangle
 pub fn spawn_wall_collision(
     mut commands: Commands,
-    wall_query: Query<(&GridCoords, &Parent), Added<Wall>>,
-    parent_query: Query<&Parent, Without<Wall>>,
-    level_query: Query<(Entity, &LevelIid)>,
+    wall_query: Query<(&GridCoords, &Parent, &Wall), Added<Wall>>,
+    parent_query: Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+    level_query: Query<(Entity, &LevelIid, &Transform)>,
     ldtk_projects: Query<&LdtkProjectHandle>,
     ldtk_project_assets: Res<Assets<LdtkProject>>,
+    mut wall_geometry: ResMut<WallGeometry>,
 ) {
-    /// Represents a wide wall that is 1 tile tall
-    /// Used to spawn wall collisions
-    #[derive(Clone, Eq, PartialEq, Debug, Default, Hash)]
-    struct Plate {
-        left: i32,
-        right: i32,
+    if wall_query.is_empty() {
+        return;
+    }
+    // Forget the levels that were unloaded, and the old walls of levels respawned by a reload
+    wall_geometry
+        .0
+        .retain(|level_entity, _| level_query.contains(*level_entity));
+    for (_, parent, _) in &wall_query {
+        if let Ok(level) = parent_query.get(parent.get()) {
+            wall_geometry.0.remove(&level.get());
+        }
     }
 
-    /// A simple rectangle type representing a wall of any size
-    struct Rect {
-        left: i32,
-        right: i32,
-        top: i32,
-        bottom: i32,
+    for material in &WALL_MATERIALS {
+        spawn_material_collision(
+            &mut commands,
+            material,
+            wall_query
+                .iter()
+                .filter(|(_, _, wall)| wall.0 == material.value)
+                .map(|(grid_coords, parent, _)| (grid_coords, parent)),
+            &parent_query,
+            &level_query,
+            &ldtk_projects,
+            &ldtk_project_assets,
+            &mut wall_geometry,
+        );
     }
+}
 
+/// Walls are only merged with walls of the same material
+#[allow(clippy::too_many_arguments)]
+fn spawn_material_collision<'a>(
+    commands: &mut Commands,
+    material: &WallMaterial,
+    walls: impl Iterator<Item = (&'a GridCoords, &'a Parent)>,
+    parent_query: &Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+    level_query: &Query<(Entity, &LevelIid, &Transform)>,
+    ldtk_projects: &Query<&LdtkProjectHandle>,
+    ldtk_project_assets: &Assets<LdtkProject>,
+    wall_geometry: &mut WallGeometry,
+) {
     // Consider where the walls are
     // storing them as GridCoords in a HashSet for quick, easy lookup
     //
@@ -60,134 +416,249 @@ pub fn spawn_wall_collision(
     // This has two consequences in the resulting collision entities:
     // 1. it forces the walls to be split along level boundaries
     // 2. it lets us easily add the collision entities as children of the appropriate level entity
-    let mut level_to_wall_locations: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
+    let level_to_wall_locations = tiles_by_level(walls, parent_query);
 
-    wall_query.iter().for_each(|(&grid_coords, parent)| {
-        // An intgrid tile's direct parent will be a layer entity, not the level entity
-        // To get the level entity, you need the tile's grandparent.
-        // This is where parent_query comes in.
+    level_query
+        .iter()
+        .for_each(|(level_entity, level_iid, level_transform)| {
+            let Some(level_walls) = level_to_wall_locations.get(&level_entity) else {
+                return;
+            };
+            let (width, height, grid_size) =
+                level_grid(level_iid, ldtk_projects, ldtk_project_assets);
+            let wall_rects = merge_tiles(level_walls, width, height);
+
+            let level_offset = level_transform.translation.truncate();
+            wall_geometry
+                .0
+                .entry(level_entity)
+                .or_default()
+                .extend(wall_rects.iter().map(|wall_rect| {
+                    bevy::math::Rect::from_center_size(
+                        wall_rect.center(grid_size) + level_offset,
+                        wall_rect.size(grid_size),
+                    )
+                }));
+
+            commands.entity(level_entity).with_children(|level| {
+                // Spawn colliders for every rectangle..
+                // Making the collider a child of the level serves two purposes:
+                // 1. Adjusts the transforms to be relative to the level for free
+                // 2. the colliders will be despawned automatically when levels unload
+                for wall_rect in wall_rects {
+                    let size = wall_rect.size(grid_size);
+                    let mut wall = level.spawn_empty();
+                    wall.insert(RigidBody::Static)
+                        .insert(Collider::rectangle(size.x, size.y))
+                        .insert(Friction::new(material.friction))
+                        .insert(Restitution::new(material.restitution))
+                        .insert(WallSurface(material.surface))
+                        .insert(Transform::from_translation(
+                            wall_rect.center(grid_size).extend(0.),
+                        ))
+                        .insert(GlobalTransform::default());
+                    if let Some(color) = material.color {
+                        wall.insert(Sprite::from_color(color, size));
+                    }
+                }
+            });
+        });
+}
+
+/// Spawns merged sensors over the hazard tiles of a level, the same way as walls
+pub fn spawn_hazard_collision(
+    mut commands: Commands,
+    hazard_query: Query<(&GridCoords, &Parent), Added<Hazard>>,
+    parent_query: Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+    level_query: Query<(Entity, &LevelIid)>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    if hazard_query.is_empty() {
+        return;
+    }
+
+    let level_to_hazard_locations = tiles_by_level(hazard_query.iter(), &parent_query);
+
+    level_query.iter().for_each(|(level_entity, level_iid)| {
+        let Some(level_hazards) = level_to_hazard_locations.get(&level_entity) else {
+            return;
+        };
+        let (width, height, grid_size) =
+            level_grid(level_iid, &ldtk_projects, &ldtk_project_assets);
+
+        commands.entity(level_entity).with_children(|level| {
+            for hazard_rect in merge_tiles(level_hazards, width, height) {
+                let size = hazard_rect.size(grid_size);
+                level.spawn((
+                    HazardZone,
+                    RigidBody::Static,
+                    Collider::rectangle(size.x, size.y),
+                    Sensor,
+                    CollisionLayers::new(LayerEnum::Hazard, [LayerEnum::Player]),
+                    Sprite::from_color(HAZARD_COLOR, size),
+                    Transform::from_translation(hazard_rect.center(grid_size).extend(0.)),
+                ));
+            }
+        });
+    });
+}
+
+/// Spawns a single collider for every chain of ramps in a level: a convex piece
+/// per gradient, and one surface line running over all of them and onto the
+/// floor at their ends, so the character controller doesn't catch on seams
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_slope_collision(
+    mut commands: Commands,
+    slope_query: Query<(&GridCoords, &Parent, &Slope), Added<Slope>>,
+    parent_query: Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+    level_query: Query<(Entity, &LevelIid, &Transform)>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+    wall_geometry: Res<WallGeometry>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if slope_query.is_empty() {
+        return;
+    }
+
+    // Same as `tiles_by_level`, keeping the kind of slope
+    let mut level_to_slopes: HashMap<Entity, Vec<(GridCoords, i32)>> = HashMap::new();
+    for (&grid_coords, parent, slope) in &slope_query {
         if let Ok(grandparent) = parent_query.get(parent.get()) {
-            level_to_wall_locations
+            level_to_slopes
                 .entry(grandparent.get())
                 .or_default()
-                .insert(grid_coords);
+                .push((grid_coords, slope.0));
         }
-    });
+    }
+    let material = materials.add(SLOPE_COLOR);
 
-    if !wall_query.is_empty() {
-        level_query.iter().for_each(|(level_entity, level_iid)| {
-            if let Some(level_walls) = level_to_wall_locations.get(&level_entity) {
-                let ldtk_project = ldtk_project_assets
-                    .get(ldtk_projects.single())
-                    .expect("Project should be loaded if level has spawned");
-
-                let level = ldtk_project
-                    .as_standalone()
-                    .get_loaded_level_by_iid(&level_iid.to_string())
-                    .expect("Spawned level should exist in LDtk project");
-
-                let LayerInstance {
-                    c_wid: width,
-                    c_hei: height,
-                    grid_size,
-                    ..
-                } = level.layer_instances()[0];
-
-                // combine wall tiles into flat "plates" in each individual row
-                let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
-
-                for y in 0..height {
-                    let mut row_plates: Vec<Plate> = Vec::new();
-                    let mut plate_start = None;
-
-                    // + 1 to the width so the algorithm "terminates" plates that touch the right edge
-                    for x in 0..width + 1 {
-                        match (plate_start, level_walls.contains(&GridCoords { x, y })) {
-                            (Some(s), false) => {
-                                row_plates.push(Plate {
-                                    left: s,
-                                    right: x - 1,
-                                });
-                                plate_start = None;
-                            }
-                            (None, true) => plate_start = Some(x),
-                            _ => (),
-                        }
-                    }
+    level_query
+        .iter()
+        .for_each(|(level_entity, level_iid, level_transform)| {
+            let Some(level_slopes) = level_to_slopes.get(&level_entity) else {
+                return;
+            };
+            let (_, _, grid_size) = level_grid(level_iid, &ldtk_projects, &ldtk_project_assets);
+            let tile_size = grid_size as f32;
 
-                    plate_stack.push(row_plates);
-                }
+            let level_offset = level_transform.translation.truncate();
+            let walls = wall_geometry
+                .0
+                .get(&level_entity)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let is_wall = |tile: IVec2| {
+                let center = (tile.as_vec2() + 0.5) * tile_size + level_offset;
+                walls.iter().any(|wall| wall.contains(center))
+            };
+            let is_floor = |tile: IVec2| is_wall(tile) && !is_wall(tile + IVec2::Y);
 
-                // combine "plates" into rectangles across multiple rows
-                let mut rect_builder: HashMap<Plate, Rect> = HashMap::new();
-                let mut prev_row: Vec<Plate> = Vec::new();
-                let mut wall_rects: Vec<Rect> = Vec::new();
-
-                // an extra empty row so the algorithm "finishes" the rects that touch the top edge
-                plate_stack.push(Vec::new());
-
-                for (y, current_row) in plate_stack.into_iter().enumerate() {
-                    for prev_plate in &prev_row {
-                        if !current_row.contains(prev_plate) {
-                            // remove the finished rect so that the same plate in the future starts a new rect
-                            if let Some(rect) = rect_builder.remove(prev_plate) {
-                                wall_rects.push(rect);
-                            }
-                        }
-                    }
-                    for plate in &current_row {
-                        rect_builder
-                            .entry(plate.clone())
-                            .and_modify(|e| e.top += 1)
-                            .or_insert(Rect {
-                                bottom: y as i32,
-                                top: y as i32,
-                                left: plate.left,
-                                right: plate.right,
-                            });
+            commands.entity(level_entity).with_children(|level| {
+                for chain in chain_slopes(merge_slopes(level_slopes)) {
+                    let surface = chain_surface(&chain, is_floor)
+                        .into_iter()
+                        .map(|point| point.as_vec2() * tile_size / 2.)
+                        .collect();
+                    let mut parts = vec![(
+                        Position::default(),
+                        Rotation::default(),
+                        Collider::polyline(surface, None),
+                    )];
+                    let mut outlines = Vec::new();
+                    for run in &chain {
+                        let outline = run.outline(grid_size);
+                        let Some(collider) = Collider::convex_hull(outline.clone()) else {
+                            warn!("Slope {:?} has no area", run);
+                            continue;
+                        };
+                        parts.push((Position::default(), Rotation::default(), collider));
+                        outlines.push(outline);
                     }
-                    prev_row = current_row;
+
+                    level.spawn((
+                        RigidBody::Static,
+                        Collider::compound(parts),
+                        Friction::new(1.0),
+                        WallSurface(Surface::Normal),
+                        Mesh2d(meshes.add(outline_mesh(&outlines))),
+                        MeshMaterial2d(material.clone()),
+                        Transform::default(),
+                    ));
                 }
+            });
+        });
+}
 
-                commands.entity(level_entity).with_children(|level| {
-                    // Spawn colliders for every rectangle..
-                    // Making the collider a child of the level serves two purposes:
-                    // 1. Adjusts the transforms to be relative to the level for free
-                    // 2. the colliders will be despawned automatically when levels unload
-                    for wall_rect in wall_rects {
-                        let width = (wall_rect.right as f32 - wall_rect.left as f32 + 1.)
-                            * grid_size as f32;
-                        let height = (wall_rect.top as f32 - wall_rect.bottom as f32 + 1.)
-                            * grid_size as f32;
-                        let x =
-                            (wall_rect.left + wall_rect.right + 1) as f32 * grid_size as f32 / 2.;
-                        let y =
-                            (wall_rect.bottom + wall_rect.top + 1) as f32 * grid_size as f32 / 2.;
-
-                        level
-                            .spawn_empty()
-                            .insert(RigidBody::Static)
-                            .insert(Collider::rectangle(width, height))
-                            .insert(Friction::new(1.0))
-                            .insert(Transform::from_xyz(x, y, 0.))
-                            .insert(GlobalTransform::default());
-                    }
-                });
+/// Spawns merged one-way platform colliders, the same way as walls.
+/// `filter_one_way_platforms` decides which contacts with them count.
+pub fn spawn_platform_collision(
+    mut commands: Commands,
+    platform_query: Query<(&GridCoords, &Parent), Added<OneWay>>,
+    parent_query: Query<&Parent, (Without<Wall>, Without<Hazard>)>,
+    level_query: Query<(Entity, &LevelIid)>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    ldtk_project_assets: Res<Assets<LdtkProject>>,
+) {
+    if platform_query.is_empty() {
+        return;
+    }
+
+    let level_to_platform_locations = tiles_by_level(platform_query.iter(), &parent_query);
+
+    level_query.iter().for_each(|(level_entity, level_iid)| {
+        let Some(level_platforms) = level_to_platform_locations.get(&level_entity) else {
+            return;
+        };
+        let (width, height, grid_size) =
+            level_grid(level_iid, &ldtk_projects, &ldtk_project_assets);
+
+        commands.entity(level_entity).with_children(|level| {
+            for platform_rect in merge_tiles(level_platforms, width, height) {
+                let size = platform_rect.size(grid_size);
+                level.spawn((
+                    OneWayPlatform,
+                    RigidBody::Static,
+                    Collider::rectangle(size.x, size.y),
+                    Friction::new(1.0),
+                    Sprite::from_color(PLATFORM_COLOR, size),
+                    Transform::from_translation(platform_rect.center(grid_size).extend(0.)),
+                ));
             }
         });
-    }
+    });
 }
 
 /// Plugin which spawns walls on appropriate LDtk int cells,
 /// then merges them together to reduce physics load.
 ///
-/// Walls are hardcoded as int cell values 1 (dirt) and 3 (stone).
+/// Walls are int cell values listed in `WALL_MATERIALS`,
+/// slopes the ones in `SLOPE_TILES`, hazards `HAZARD_CELL`
+/// and one-way platforms `ONE_WAY_CELL`.
 pub struct WallPlugin;
 
 impl Plugin for WallPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, spawn_wall_collision)
-            .register_ldtk_int_cell::<WallBundle>(1); //dirt
-            //.register_ldtk_int_cell::<WallBundle>(3); //stone
+        for material in &WALL_MATERIALS {
+            app.register_ldtk_int_cell::<WallBundle>(material.value);
+        }
+        for (value, ..) in SLOPE_TILES {
+            app.register_ldtk_int_cell::<SlopeBundle>(value);
+        }
+        app.init_resource::<WallGeometry>()
+            .add_systems(
+                Update,
+                (
+                    spawn_wall_collision,
+                    // Ramps run onto the floor the walls make
+                    spawn_slope_collision.after(spawn_wall_collision),
+                    spawn_hazard_collision,
+                    spawn_platform_collision,
+                ),
+            )
+            .register_ldtk_int_cell::<HazardBundle>(HAZARD_CELL)
+            .register_ldtk_int_cell::<OneWayBundle>(ONE_WAY_CELL);
     }
 }